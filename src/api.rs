@@ -1,9 +1,15 @@
 use std::thread;
-use anyhow::anyhow;
+use crate::error::Error;
+use crate::events::{Event, Events};
+use crate::logging;
 use crossbeam::channel::unbounded;
-use fxhash::FxHashMap;
-use reqwest::blocking::Client;
+use fxhash::{FxHashMap, FxHashSet};
+use log::{info, trace, warn};
+use reqwest::StatusCode;
+use reqwest::blocking::{Client, RequestBuilder, Response};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -12,11 +18,113 @@ pub struct FileInfo {
     pub object_name: String,
     pub checksum: Option<String>,
     pub is_directory: bool,
+    #[serde(default)]
+    pub length: u64,
+    #[serde(default)]
+    pub last_changed: String,
+    #[serde(default)]
+    pub content_type: String,
 }
 
 #[derive(Debug)]
 pub struct FileMeta {
     pub checksum: Option<[u8; 32]>,
+    pub content_type: Option<String>,
+    /// Size in bytes, if known
+    pub length: Option<u64>,
+}
+
+/// The files and directories found when listing a subtree of the storage zone
+#[derive(Debug, Default)]
+pub struct RemoteListing {
+    pub files: FxHashMap<String, FileMeta>,
+    /// Directory paths with a trailing `/`
+    pub directories: FxHashSet<String>,
+    /// Directories that could not be listed, so files below them may be missing from `files`
+    pub unlisted: Vec<String>,
+}
+
+impl RemoteListing {
+    /// Whether every directory was listed, so that a file missing from `files` does not exist
+    pub fn is_complete(&self) -> bool {
+        self.unlisted.is_empty()
+    }
+
+    /// Fail with the directories that could not be listed, if there are any
+    pub fn ensure_complete(&self) -> Result<(), Error> {
+        match self.is_complete() {
+            true => Ok(()),
+            false => Err(Error::IncompleteListing {
+                unlisted: self.unlisted.clone(),
+            }),
+        }
+    }
+}
+
+/// Everything found by `discover_files`
+#[derive(Debug, Default)]
+struct Discovery {
+    files: Vec<FileInfo>,
+    /// Directories that could not be listed
+    unlisted: Vec<String>,
+}
+
+/// The parent directory of `path` with a trailing `/`, and the name of `path` in it
+fn split_parent(path: &str) -> (String, &str) {
+    let path = path.trim_start_matches('/').trim_end_matches('/');
+    match path.rsplit_once('/') {
+        Some((parent, name)) => (format!("{parent}/"), name),
+        None => (String::new(), path),
+    }
+}
+
+/// How often listing a directory is tried before giving up on it
+const LIST_ATTEMPTS: u32 = 3;
+/// Delay before the first retry of a listing, doubled for every further retry
+const LIST_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Send `request` for `path` with `key`, turning unsuccessful responses into errors
+pub fn send(request: RequestBuilder, key: &str, path: &str) -> Result<Response, Error> {
+    send_retry(request, key, path, 0)
+}
+
+/// Like `send`, for a request that was tried `retries` times before. Traces the request
+fn send_retry(
+    request: RequestBuilder,
+    key: &str,
+    path: &str,
+    retries: u32,
+) -> Result<Response, Error> {
+    let path = if path.is_empty() { "/" } else { path };
+    logging::redact(key);
+    let network = |source| Error::Network {
+        path: path.to_string(),
+        source,
+    };
+    let (client, request) = request.header("AccessKey", key).build_split();
+    let request = request.map_err(network)?;
+    let (method, url) = (request.method().clone(), request.url().clone());
+    let started = Instant::now();
+    let response = client.execute(request);
+    let latency_ms = crate::millis(started.elapsed());
+    match &response {
+        Ok(response) => trace!(
+            method:% = method, url:% = url, status = response.status().as_u16(), latency_ms,
+            retries; "HTTP request"
+        ),
+        Err(e) => trace!(
+            method:% = method, url:% = url, latency_ms, retries, error:% = e;
+            "HTTP request failed"
+        ),
+    }
+    Error::check(response.map_err(network)?, path)
+}
+
+fn invalid_response(path: &str) -> impl FnOnce(reqwest::Error) -> Error + '_ {
+    move |source| Error::InvalidResponse {
+        path: path.to_string(),
+        source,
+    }
 }
 
 #[derive(Clone)]
@@ -25,6 +133,7 @@ pub struct StorageZoneClient {
     access_key: String,
     endpoint: String,
     storage_zone: String,
+    events: Events,
 }
 
 impl StorageZoneClient {
@@ -34,123 +143,336 @@ impl StorageZoneClient {
             access_key,
             endpoint,
             storage_zone,
+            events: Events::default(),
         }
     }
 
-    pub fn read_file(&self, path: &str) -> anyhow::Result<String> {
-        let response = self
-            .client
-            .get(self.url_for(path))
-            .header("AccessKey", self.access_key.as_str())
-            .send()?;
-        if response.status().is_success() {
-            Ok(response.text()?)
+    /// Report what happens with this client to `events`
+    pub fn with_events(self, events: Events) -> Self {
+        StorageZoneClient { events, ..self }
+    }
+
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Send `request` for `path`, reporting it as `operation` after `retries` earlier attempts
+    fn send(
+        &self,
+        operation: &str,
+        request: RequestBuilder,
+        path: &str,
+        retries: u32,
+    ) -> Result<Response, Error> {
+        let started = Instant::now();
+        let response = send_retry(request, self.access_key.as_str(), path, retries);
+        self.events.emit(Event::Request {
+            operation,
+            path,
+            status: match &response {
+                Ok(response) => Some(response.status().as_u16()),
+                Err(e) => e.status().map(|status| status.as_u16()),
+            },
+            duration_ms: crate::millis(started.elapsed()),
+            retries,
+        });
+        response
+    }
+
+    pub fn download_file(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let response = self.send("get", self.client.get(self.url_for(path)), path, 0)?;
+        let content = response.bytes().map_err(invalid_response(path))?;
+        Ok(content.to_vec())
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<String, Error> {
+        let response = self.send("get", self.client.get(self.url_for(path)), path, 0)?;
+        response.text().map_err(invalid_response(path))
+    }
+
+    /// Read the lockfile, as the `lock` operation since finding none is what taking a lock hopes
+    /// for rather than a failed request
+    pub fn read_lock(&self, lockfile: &str) -> Result<String, Error> {
+        let response = self.send("lock", self.client.get(self.url_for(lockfile)), lockfile, 0)?;
+        response.text().map_err(invalid_response(lockfile))
+    }
+
+    fn url_for(&self, path: &str) -> String {
+        if self.endpoint.contains("://") {
+            format!("{}/{}/{path}", self.endpoint, self.storage_zone)
         } else {
-            Err(anyhow!("Unable to read: {:?}", response.status()))
+            format!("https://{}/{}/{path}", self.endpoint, self.storage_zone)
         }
     }
 
-    fn url_for(&self, path: &str) -> String {
-        format!("https://{}/{}/{path}", self.endpoint, self.storage_zone)
+    /// The path of a listed file or directory relative to the root of the storage zone
+    pub fn name_of(&self, file: &FileInfo) -> String {
+        let trim_prefix = format!("/{}/", self.storage_zone);
+        let name = format!(
+            "{}{}",
+            file.path.trim_start_matches(trim_prefix.as_str()),
+            file.object_name
+        );
+        if file.is_directory {
+            format!("{name}/")
+        } else {
+            name
+        }
+    }
+
+    pub fn ls_dir(&self, path: &str) -> Result<Vec<FileInfo>, Error> {
+        self.list_dir(path, 0)
+    }
+
+    fn list_dir(&self, path: &str, retries: u32) -> Result<Vec<FileInfo>, Error> {
+        let request = self.client.get(self.url_for(path));
+        let response = self.send("list", request, path, retries)?;
+        response.json().map_err(invalid_response(path))
+    }
+
+    /// Look up a single file or directory by listing its parent directory
+    pub fn stat(&self, path: &str) -> Result<Option<FileInfo>, Error> {
+        let (parent, name) = split_parent(path);
+        Ok(self
+            .ls_dir(parent.as_str())?
+            .into_iter()
+            .find(|file| file.object_name == name))
+    }
+
+    /// Look up the files among `paths` that exist, listing each of their parent directories once
+    pub fn stat_all(&self, paths: &[&str]) -> Result<FxHashMap<String, FileInfo>, Error> {
+        let mut names: FxHashMap<String, FxHashSet<&str>> = FxHashMap::default();
+        for path in paths {
+            let (parent, name) = split_parent(path);
+            names.entry(parent).or_default().insert(name);
+        }
+        let mut found = FxHashMap::default();
+        for (parent, names) in names {
+            for file in self.ls_dir(parent.as_str())? {
+                if !file.is_directory && names.contains(file.object_name.as_str()) {
+                    found.insert(format!("{parent}{}", file.object_name), file);
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Move a file within the storage zone by copying it and deleting the original once the
+    /// checksum of the copy has been verified
+    pub fn move_file(&self, from: &str, to: &str) -> Result<(), Error> {
+        let source = self.stat_file(from)?;
+        self.move_listed(&source, from, to)
     }
 
-    fn ls_dir(&self, path: &str) -> anyhow::Result<Vec<FileInfo>> {
-        let response = self
+    /// Like `move_file`, for a `source` found in a listing already
+    pub fn move_listed(&self, source: &FileInfo, from: &str, to: &str) -> Result<(), Error> {
+        // Copying a file onto itself and deleting the original would lose it
+        if from.trim_start_matches('/') == to.trim_start_matches('/') {
+            return Err(Error::SafetyLimit(format!(
+                "Refusing to move {from} onto itself"
+            )));
+        }
+        self.copy_listed(source, from, to)?;
+        self.delete_file(from)
+    }
+
+    /// Copy a file within the storage zone by downloading it and uploading it to the new
+    /// location, verifying the checksum of both
+    pub fn copy_file(&self, from: &str, to: &str) -> Result<(), Error> {
+        let source = self.stat_file(from)?;
+        self.copy_listed(&source, from, to)
+    }
+
+    fn stat_file(&self, path: &str) -> Result<FileInfo, Error> {
+        self.stat(path)?
+            .filter(|file| !file.is_directory)
+            .ok_or_else(|| Error::NotFound {
+                path: path.to_string(),
+            })
+    }
+
+    fn copy_listed(&self, source: &FileInfo, from: &str, to: &str) -> Result<(), Error> {
+        let content = self.download_file(from)?;
+        let digest = hex::encode_upper(Sha256::digest(&content));
+        if let Some(checksum) = &source.checksum
+            && !checksum.eq_ignore_ascii_case(&digest)
+        {
+            return Err(Error::ChecksumMismatch {
+                path: from.to_string(),
+                after: "downloading",
+            });
+        }
+        let content_type = if source.content_type.is_empty() {
+            infer::get(&content).map(|t| t.mime_type())
+        } else {
+            Some(source.content_type.as_str())
+        };
+        // bunny.net refuses an upload that does not match its Checksum header with 400
+        let request = self
             .client
-            .get(self.url_for(path))
-            .header("AccessKey", self.access_key.as_str())
-            .send()?;
-        Ok(response.json()?)
+            .put(self.url_for(to))
+            .header(
+                "Content-Type",
+                content_type.unwrap_or("application/octet-stream"),
+            )
+            .header("Checksum", digest.as_str())
+            .body(content);
+        match self.send("put", request, to, 0) {
+            Err(Error::Http { status, .. }) if status == StatusCode::BAD_REQUEST => {
+                Err(Error::ChecksumMismatch {
+                    path: to.to_string(),
+                    after: "uploading",
+                })
+            }
+            sent => sent.map(|_| ()),
+        }
     }
 
-    fn concurrent_discover_files(
+    /// List everything in the subtree at `path`, both files and directories, except for the
+    /// subtrees that start with a prefix in `skip`. Directories that cannot be listed are retried,
+    /// and reported as unlisted once they failed `LIST_ATTEMPTS` times.
+    fn discover_files(
         &self,
         path: &str,
         skip: &[String],
         concurrency: usize,
-    ) -> anyhow::Result<Vec<FileInfo>> {
+    ) -> anyhow::Result<Discovery> {
         let (post_work, receive_work) = unbounded();
         let (post_result, receive_result) = unbounded();
 
-        post_work.send(path.to_string())?;
+        post_work.send((path.to_string(), 1))?;
 
         thread::scope(|scope| {
-            let mut files = vec![];
+            let mut discovery = Discovery::default();
 
             // Spawn workers
-            let mut workers = Vec::with_capacity(concurrency);
             for _ in 0..concurrency {
                 let receive_work = receive_work.clone();
                 let send_result = post_result.clone();
-                workers.push(scope.spawn(move || {
-                    while let Ok(path) = receive_work.recv() {
-                        send_result.send(self.ls_dir(path.as_str()))?;
+                scope.spawn(move || {
+                    while let Ok((path, attempt)) = receive_work.recv() {
+                        if attempt > 1 {
+                            thread::sleep(LIST_RETRY_DELAY * 2u32.pow(attempt - 2));
+                        }
+                        let listed = self.list_dir(path.as_str(), attempt - 1);
+                        send_result.send((path, attempt, listed))?;
                     }
                     // Channel closed
                     Ok::<(), anyhow::Error>(())
-                }));
+                });
             }
 
-            let global_prefix = format!("/{}/", self.storage_zone);
             let mut responses_needed = 1;
 
             while responses_needed > 0 {
-                let new = receive_result.recv()??;
+                let (path, attempt, listed) = receive_result.recv()?;
                 responses_needed -= 1;
+                let new = match listed {
+                    Ok(new) => new,
+                    Err(e) if attempt < LIST_ATTEMPTS => {
+                        self.events.emit(Event::ListRetry {
+                            path: path.as_str(),
+                            attempt: attempt + 1,
+                            error: format!("{e:#}"),
+                        });
+                        responses_needed += 1;
+                        post_work.send((path, attempt + 1))?;
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("Unable to list {path}: {e:#}");
+                        self.events.emit(Event::ListFailed {
+                            path: path.as_str(),
+                            error: format!("{e:#}"),
+                        });
+                        discovery.unlisted.push(path);
+                        continue;
+                    }
+                };
+                let directories = new.iter().filter(|child| child.is_directory).count();
+                self.events.emit(Event::Listed {
+                    path: path.as_str(),
+                    files: new.len() - directories,
+                    directories,
+                });
                 for child in new {
                     if child.is_directory {
-                        let subtree = format!(
-                            "{}/{}/",
-                            child
-                                .path
-                                .trim_start_matches(global_prefix.as_str())
-                                .trim_end_matches('/'),
-                            child.object_name.as_str()
-                        );
+                        let subtree = self.name_of(&child);
                         if skip.iter().any(|skip| subtree.starts_with(skip)) {
                             continue;
                         }
                         responses_needed += 1;
-                        post_work.send(subtree)?;
-                    } else {
-                        files.push(child);
+                        post_work.send((subtree, 1))?;
                     }
+                    discovery.files.push(child);
                 }
             }
             // Close channel to shut down workers
             drop(post_work);
-            Ok::<Vec<_>, anyhow::Error>(files)
+            discovery.unlisted.sort();
+            Ok::<Discovery, anyhow::Error>(discovery)
         })
     }
 
+    /// Like `discover_files`, but fail unless every directory could be listed
+    pub fn concurrent_discover_files(
+        &self,
+        path: &str,
+        skip: &[String],
+        concurrency: usize,
+    ) -> anyhow::Result<Vec<FileInfo>> {
+        let Discovery { files, unlisted } = self.discover_files(path, skip, concurrency)?;
+        if !unlisted.is_empty() {
+            return Err(Error::IncompleteListing { unlisted }.into());
+        }
+        Ok(files)
+    }
+
     pub fn list_files(
         &self,
         path: &str,
         skip: &[String],
         concurrency: usize,
-    ) -> anyhow::Result<FxHashMap<String, FileMeta>> {
-        let files = self.concurrent_discover_files(path, skip, concurrency)?;
-        let mut files_by_name = FxHashMap::default();
-        let trim_prefix = format!("/{}/", self.storage_zone);
+    ) -> anyhow::Result<RemoteListing> {
+        let started = Instant::now();
+        let Discovery { files, unlisted } = self.discover_files(path, skip, concurrency)?;
+        let mut listing = RemoteListing {
+            unlisted,
+            ..Default::default()
+        };
         for fi in files {
+            if fi.is_directory {
+                listing.directories.insert(self.name_of(&fi));
+                continue;
+            }
             let checksum = fi
                 .checksum
+                .as_ref()
                 .map(|hex_checksum| {
                     let mut checksum = [0; 32];
                     hex::decode_to_slice(hex_checksum.as_bytes(), &mut checksum)?;
                     Ok::<[u8; 32], anyhow::Error>(checksum)
                 })
                 .transpose()?;
-            files_by_name.insert(
-                format!(
-                    "{}{}",
-                    fi.path.trim_start_matches(trim_prefix.as_str()),
-                    fi.object_name
-                ),
-                FileMeta { checksum },
+            let content_type = Some(fi.content_type.clone()).filter(|ct| !ct.is_empty());
+            listing.files.insert(
+                self.name_of(&fi),
+                FileMeta {
+                    checksum,
+                    content_type,
+                    length: Some(fi.length),
+                },
             );
         }
-        Ok(files_by_name)
+        let duration_ms = crate::millis(started.elapsed());
+        let files = listing.files.len();
+        info!(path, files, duration_ms; "Listed the storage zone");
+        self.events.emit(Event::ListingDone {
+            path,
+            files,
+            duration_ms,
+        });
+        Ok(listing)
     }
 
     pub fn put_file(
@@ -158,40 +480,87 @@ impl StorageZoneClient {
         path: &str,
         body: Vec<u8>,
         content_type: Option<&str>,
-    ) -> anyhow::Result<()> {
-        let url = self.url_for(path);
-
-        let response = self
+    ) -> Result<(), Error> {
+        let request = self
             .client
-            .put(url)
-            .header("AccessKey", self.access_key.as_str())
+            .put(self.url_for(path))
             .header(
                 "Content-Type",
                 content_type.unwrap_or("application/octet-stream"),
             )
-            .body(body)
-            .send()?;
+            .body(body);
+        self.send("put", request, path, 0).map(|_| ())
+    }
 
-        if response.status().is_success() {
-            Ok(())
-        } else {
-            Err(anyhow!("Request failed: {:?}", response.status()))
+    pub fn delete_file(&self, path: &str) -> Result<(), Error> {
+        self.send("delete", self.client.delete(self.url_for(path)), path, 0)
+            .map(|_| ())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PullZone {
+    origin_url: String,
+}
+
+/// Reads and changes the settings of a pull zone through the bunny.net API
+pub struct PullZoneClient {
+    client: Client,
+    api_key: String,
+    api_endpoint: String,
+    pull_zone: u64,
+}
+
+impl PullZoneClient {
+    pub fn new(api_key: String, api_endpoint: String, pull_zone: u64) -> Self {
+        PullZoneClient {
+            client: Client::new(),
+            api_key,
+            api_endpoint,
+            pull_zone,
         }
     }
 
-    pub fn delete_file(&self, path: &str) -> anyhow::Result<()> {
-        let response = self
-            .client
-            .delete(self.url_for(path))
-            .header("AccessKey", self.access_key.as_str())
-            .send()?;
-        Ok(response.error_for_status().map(|_| ())?)
+    fn url(&self) -> String {
+        format!(
+            "{}/pullzone/{}",
+            self.api_endpoint.trim_end_matches('/'),
+            self.pull_zone
+        )
+    }
+
+    /// The URL the pull zone fetches files from
+    pub fn origin_url(&self) -> Result<String, Error> {
+        let url = self.url();
+        let response = send(self.client.get(&url), self.api_key.as_str(), &url)?;
+        let pull_zone: PullZone = response.json().map_err(invalid_response(&url))?;
+        Ok(pull_zone.origin_url)
+    }
+
+    /// Point the pull zone to a new origin URL, in a single API call
+    pub fn set_origin_url(&self, origin_url: &str) -> Result<(), Error> {
+        let body = serde_json::json!({ "OriginUrl": origin_url });
+        let url = self.url();
+        send(
+            self.client.post(&url).json(&body),
+            self.api_key.as_str(),
+            &url,
+        )
+        .map(|_| ())
+    }
+
+    /// Purge every file of the pull zone from the cache
+    pub fn purge_cache(&self) -> Result<(), Error> {
+        let url = format!("{}/purgeCache", self.url());
+        send(self.client.post(&url), self.api_key.as_str(), &url).map(|_| ())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::FakeStorageZone;
 
     const EX: &str = "{
     \"StorageZoneName\": \"eugene-docs\",
@@ -211,4 +580,23 @@ mod tests {
     fn test_parse() {
         let _: FileInfo = serde_json::from_str(EX).unwrap();
     }
+
+    #[test]
+    fn retries_and_reports_unlisted_directories() {
+        let zone = FakeStorageZone::start("listing-zone");
+        zone.insert("site/index.html", b"index");
+        zone.insert("site/flaky/a.css", b"a");
+        zone.insert("site/broken/b.css", b"b");
+        zone.fail_times("site/flaky/", "503 Service Unavailable", 2);
+        zone.fail("site/broken/", "500 Internal Server Error");
+        let client = zone.client();
+
+        let listing = client.list_files("site/", &[], 2).unwrap();
+        assert!(!listing.is_complete());
+        assert_eq!(listing.unlisted, vec!["site/broken/"]);
+        let mut files: Vec<_> = listing.files.keys().map(String::as_str).collect();
+        files.sort();
+        assert_eq!(files, vec!["site/flaky/a.css", "site/index.html"]);
+        assert!(client.concurrent_discover_files("site/", &[], 2).is_err());
+    }
 }
@@ -0,0 +1,243 @@
+use crate::cli::CheckArgs;
+use crate::local_path;
+use crate::planning::{
+    Execution, Reason, SyncAction, SyncPlan, plan_empty_directories, plan_execution, plan_sync,
+};
+use crate::{SyncJob, init_sync, with_reserved_prefixes};
+use anyhow::Context;
+use std::fs;
+
+/// Exit code when files must be uploaded to make the storage zone match local_path
+pub const UPLOADS_PENDING: i32 = 2;
+/// Exit code when files must be deleted to make the storage zone match local_path
+pub const DELETES_PENDING: i32 = 4;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Change {
+    New,
+    Changed,
+    Delete,
+}
+
+impl Change {
+    pub fn marker(&self) -> char {
+        match self {
+            Change::New => '+',
+            Change::Changed => '~',
+            Change::Delete => '-',
+        }
+    }
+}
+
+/// The changes a sync would make, sorted by path. The lockfile is never included.
+pub fn pending_changes(
+    job: &[SyncPlan],
+    empty_directories: &[String],
+    lockfile: &str,
+) -> anyhow::Result<Vec<(String, Change, Reason)>> {
+    let mut changes = vec![];
+    for plan in job {
+        let Execution {
+            remote,
+            action,
+            reason,
+        } = plan_execution(plan, fs::read)?;
+        let change = match (plan, action) {
+            (_, SyncAction::Ignore) | (SyncPlan::Keep { .. }, _) => None,
+            (SyncPlan::Put { .. }, _) => Some(Change::New),
+            (SyncPlan::Replace { .. }, _) => Some(Change::Changed),
+            (SyncPlan::Delete { .. }, _) if remote == lockfile => None,
+            (SyncPlan::Delete { .. }, _) => Some(Change::Delete),
+        };
+        if let Some(change) = change {
+            changes.push((remote.to_string(), change, reason));
+        }
+    }
+    changes.extend(
+        empty_directories
+            .iter()
+            .map(|directory| (directory.clone(), Change::Delete, Reason::EmptyDirectory)),
+    );
+    changes.sort();
+    Ok(changes)
+}
+
+fn exit_code(changes: &[(String, Change, Reason)]) -> i32 {
+    let uploads = changes
+        .iter()
+        .any(|(_, change, _)| matches!(change, Change::New | Change::Changed));
+    let deletes = changes
+        .iter()
+        .any(|(_, change, _)| matches!(change, Change::Delete));
+    let mut code = 0;
+    if uploads {
+        code |= UPLOADS_PENDING;
+    }
+    if deletes {
+        code |= DELETES_PENDING;
+    }
+    code
+}
+
+/// Print a line for each pending change and a summary, returning the exit code for check
+pub fn report_changes(
+    job: &[SyncPlan],
+    empty_directories: &[String],
+    lockfile: &str,
+) -> anyhow::Result<i32> {
+    let changes = pending_changes(job, empty_directories, lockfile)?;
+    for (remote, change, reason) in &changes {
+        println!("{} {remote} ({reason})", change.marker());
+    }
+    let count = |kind: Change| {
+        changes
+            .iter()
+            .filter(|(_, change, _)| *change == kind)
+            .count()
+    };
+    if changes.is_empty() {
+        eprintln!("No changes pending");
+    } else {
+        eprintln!(
+            "{} new, {} changed, {} to delete",
+            count(Change::New),
+            count(Change::Changed),
+            count(Change::Delete)
+        );
+    }
+    Ok(exit_code(&changes))
+}
+
+/// Compare local_path to the storage zone without changing anything, returning the exit code
+pub fn do_check(args: CheckArgs) -> anyhow::Result<i32> {
+    let CheckArgs {
+        endpoint,
+        access_key,
+        local_path,
+        storage_zone,
+        path,
+        lockfile,
+        ignore,
+        concurrency,
+        from_list,
+    } = args;
+    let ignore = with_reserved_prefixes(ignore);
+    let concurrency = concurrency.unwrap_or_else(num_cpus::get);
+
+    let SyncJob {
+        client,
+        path,
+        local_path,
+    } = init_sync(access_key, local_path, path, storage_zone, endpoint)?;
+    let local = local_path::local_files(from_list.as_deref(), local_path.as_str(), path.as_str())?;
+    let remote = client.list_files(path.as_str(), &ignore, concurrency)?;
+    let job = plan_sync(&local, &remote.files, &ignore, remote.is_complete());
+    let empty_directories = plan_empty_directories(&local, &remote, &ignore);
+    let code = report_changes(&job, &empty_directories, lockfile.as_str())?;
+    remote
+        .ensure_complete()
+        .context("Unable to tell which files to delete")?;
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::FileMeta;
+    use crate::error::{self, Error};
+    use crate::testing::FakeStorageZone;
+    use fxhash::FxHashMap;
+    use sha2::{Digest, Sha256};
+    use std::path::PathBuf;
+
+    #[test]
+    fn reports_pending_changes_and_exit_code() {
+        let readme = fs::read("README.md").unwrap();
+        let mut local = FxHashMap::default();
+        local.insert("README.md".to_string(), PathBuf::from("README.md").into());
+        local.insert("Cargo.toml".to_string(), PathBuf::from("Cargo.toml").into());
+        local.insert("LICENSE.md".to_string(), PathBuf::from("LICENSE.md").into());
+        let mut remote = FxHashMap::default();
+        remote.insert(
+            "README.md".to_string(),
+            FileMeta {
+                checksum: Some(Sha256::digest(&readme).into()),
+                content_type: None,
+                length: None,
+            },
+        );
+        remote.insert(
+            "Cargo.toml".to_string(),
+            FileMeta {
+                checksum: Some([0; 32]),
+                content_type: None,
+                length: None,
+            },
+        );
+        remote.insert(
+            "old.html".to_string(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
+        remote.insert(
+            ".thumper.lock".to_string(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
+
+        let job = plan_sync(&local, &remote, &[], true);
+        let changes = pending_changes(&job, &["old/".to_string()], ".thumper.lock").unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    "Cargo.toml".to_string(),
+                    Change::Changed,
+                    Reason::ChecksumMismatch
+                ),
+                ("LICENSE.md".to_string(), Change::New, Reason::NewFile),
+                (
+                    "old.html".to_string(),
+                    Change::Delete,
+                    Reason::MissingLocally
+                ),
+                ("old/".to_string(), Change::Delete, Reason::EmptyDirectory),
+            ]
+        );
+        assert_eq!(exit_code(&changes), UPLOADS_PENDING | DELETES_PENDING);
+        assert_eq!(exit_code(&changes[..2]), UPLOADS_PENDING);
+        assert_eq!(exit_code(&changes[2..]), DELETES_PENDING);
+        assert_eq!(exit_code(&[]), 0);
+    }
+
+    #[test]
+    fn fails_when_listing_is_incomplete() {
+        let zone = FakeStorageZone::start("check-zone");
+        zone.insert("site/broken/old.css", b"old");
+        zone.fail("site/broken/", "500 Internal Server Error");
+        let args = CheckArgs {
+            endpoint: zone.endpoint(),
+            access_key: Some("secret".into()),
+            local_path: "src".into(),
+            storage_zone: "check-zone".into(),
+            path: "site".into(),
+            lockfile: ".thumper.lock".into(),
+            ignore: vec![],
+            concurrency: Some(1),
+            from_list: None,
+        };
+
+        let e = do_check(args).unwrap_err();
+        assert!(matches!(
+            error::find(&e),
+            Some(Error::IncompleteListing { .. })
+        ));
+        assert_eq!(error::report(&e, error::ErrorFormat::Text), 17);
+    }
+}
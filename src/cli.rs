@@ -1,3 +1,9 @@
+use crate::error::ErrorFormat;
+use crate::events::Output;
+use crate::logging::LogFormat;
+use crate::summary::SummaryFormat;
+use crate::trash::parse_age;
+use chrono::Duration;
 use clap::{Parser, Subcommand};
 
 #[derive(Subcommand)]
@@ -5,7 +11,98 @@ pub enum Action {
     /// Sync a local folder to a path within a bunny.net Storage Zone
     Sync {
         #[command(flatten)]
-        args: SyncArgs,
+        args: Box<SyncArgs>,
+    },
+    /// Check whether a path within a bunny.net Storage Zone is identical to a local folder
+    #[command(
+        long_about = "Check whether a path within a bunny.net Storage Zone is identical to a local folder
+
+Nothing is changed and no lock is taken. Prints a line for each pending change, + for new files,
+~ for changed files and - for deletions.
+
+Exits with 0 when there is nothing to sync, 2 when uploads are pending, 4 when deletes are pending,
+6 when both are pending, and with the exit codes of errors listed in the README otherwise."
+    )]
+    Check {
+        #[command(flatten)]
+        args: CheckArgs,
+    },
+    /// Save the changes a sync would make to a file, to review them and run them with apply
+    Plan {
+        #[command(flatten)]
+        args: PlanArgs,
+    },
+    /// Run a plan saved by thumper plan, if nothing changed since it was made
+    Apply {
+        #[command(flatten)]
+        args: ApplyArgs,
+    },
+    /// Download a path within a bunny.net Storage Zone to a local folder
+    Pull {
+        #[command(flatten)]
+        args: PullArgs,
+    },
+    /// List files in a bunny.net Storage Zone
+    Ls {
+        #[command(flatten)]
+        args: LsArgs,
+    },
+    /// Print a file in a bunny.net Storage Zone
+    Cat {
+        #[command(flatten)]
+        args: CatArgs,
+    },
+    /// Upload a single file to a bunny.net Storage Zone
+    Put {
+        #[command(flatten)]
+        args: PutArgs,
+    },
+    /// Delete a file or directory in a bunny.net Storage Zone
+    Rm {
+        #[command(flatten)]
+        args: RmArgs,
+    },
+    /// Move a file within a bunny.net Storage Zone
+    Mv {
+        #[command(flatten)]
+        args: MvArgs,
+    },
+    /// List, restore or empty files that sync --trash moved to .thumper-trash/
+    Trash {
+        #[command(subcommand)]
+        command: TrashAction,
+    },
+    /// List the previous versions of a file that sync --history kept
+    History {
+        #[command(flatten)]
+        args: HistoryArgs,
+    },
+    /// Bring back a previous version of a file that sync --history kept
+    Restore {
+        #[command(flatten)]
+        args: RestoreArgs,
+    },
+    /// Upload a local folder as a new release and switch a pull zone to it once it is complete
+    #[command(
+        long_about = "Upload a local folder as a new release and switch a pull zone to it once it is complete
+
+Each release is uploaded to its own directory, releases/<release-id>/ by default. Only when every
+file is in place is the origin URL of the pull zone changed to point to the new release, in a
+single API call, so visitors never see a half-done deploy. Old releases beyond --keep are deleted."
+    )]
+    Release {
+        #[command(flatten)]
+        args: ReleaseArgs,
+    },
+    /// Switch a pull zone back to the release before the current one
+    Rollback {
+        #[command(flatten)]
+        args: RollbackArgs,
+    },
+    /// List or remove preview deploys made with sync --preview
+    Previews {
+        #[command(subcommand)]
+        command: PreviewsAction,
     },
     /// Provide shell completions
     Completions {
@@ -56,6 +153,20 @@ HTML at the end, to ensure other assets like CSS are already updated by the time
 pub struct Cli {
     #[command(subcommand)]
     pub command: Action,
+    /// How to print errors, json prints an object with the error code, exit code, message and the
+    /// path and HTTP status involved
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Log more, -v logs progress and prints a line for each file, -vv also traces every HTTP
+    /// request
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+    /// How to print log lines, json prints one object per line
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
 }
 
 #[derive(Parser)]
@@ -87,11 +198,555 @@ pub struct SyncArgs {
     /// Do not delete anything in the storage zone paths that start with this prefix (can pass multiple times)
     #[arg(short, long)]
     pub ignore: Vec<String>,
+    /// Set from the global --verbose
+    #[arg(skip)]
+    pub verbose: bool,
+    /// Number of threads to use when calling bunny.net API (default to number of cpus)
+    #[arg(short, long)]
+    pub concurrency: Option<usize>,
+    /// Sync the files in this list instead of everything in local_path. One path relative to
+    /// local_path per line, or a .json array of objects with path, sha256 and content_type
+    #[arg(long)]
+    pub from_list: Option<String>,
+    /// With --dry-run, show a diff of changed text files and a size and checksum summary of other changed files
+    #[arg(long, default_value_t = false, requires = "dry_run")]
+    pub diff: bool,
+    /// Files larger than this many bytes get a summary instead of a diff
+    #[arg(long, default_value_t = 256 * 1024)]
+    pub diff_max_bytes: usize,
+    /// Review the changes grouped by directory and approve or deselect them before they run.
+    /// Ignored when stdin is not a terminal
+    #[arg(long, default_value_t = false)]
+    pub interactive: bool,
+    #[command(flatten)]
+    pub delete_limits: DeleteLimitArgs,
+    /// Move deleted files to .thumper-trash/<deploy-id>/ in the storage zone instead of deleting
+    /// them, so that they can be brought back with thumper trash restore
+    #[arg(long, default_value_t = false)]
+    pub trash: bool,
+    #[command(flatten)]
+    pub history: KeepHistoryArgs,
+    /// Deploy a preview with this name, like a branch name, to --preview-template instead of --path
+    #[arg(long)]
+    pub preview: Option<String>,
+    /// Where previews are deployed in the storage zone, {branch} is replaced by the preview name
+    #[arg(long, default_value = "previews/{branch}/")]
+    pub preview_template: String,
+    /// Git ref to record in the preview marker (default to the commit checked out in the working directory)
+    #[arg(long, requires = "preview")]
+    pub git_ref: Option<String>,
+    /// Continue a sync that was interrupted, skipping the files it already synced, unless local
+    /// files changed since. Takes over the lock of the interrupted sync
+    #[arg(long, default_value_t = false, conflicts_with = "dry_run")]
+    pub resume: bool,
+    /// File to record completed files in for --resume (default to a file in the temp directory)
+    #[arg(long)]
+    pub journal: Option<String>,
+    /// Run every upload even when some fail and report all failures at the end. Deletes are
+    /// skipped if any upload failed
+    #[arg(long, default_value_t = false)]
+    pub keep_going: bool,
+    /// Print a line for each job with text, or one JSON object per event of the sync with jsonl
+    #[arg(long, value_enum, default_value_t = Output::Text)]
+    pub output: Output,
+    /// Don't show progress of listing and uploading on stderr. It is also left out with
+    /// --verbose, --dry-run and --interactive
+    #[arg(long, default_value_t = false)]
+    pub no_progress: bool,
+    /// Print a summary of counts, bytes and timings to stderr when the sync is done
+    #[arg(long, value_enum, default_value_t = SummaryFormat::Text)]
+    pub summary: SummaryFormat,
+    /// Write Prometheus metrics of the sync to this file for the node_exporter textfile
+    /// collector, like /var/lib/node_exporter/thumper.prom
+    #[arg(long)]
+    pub metrics_file: Option<String>,
+    /// POST a JSON summary of the sync to this URL when it finishes, can pass multiple times
+    #[arg(long)]
+    pub notify_url: Vec<String>,
+    /// Sign notifications with HMAC-SHA256 in the X-Thumper-Signature-256 header - looked up in
+    /// environment variable THUMPER_NOTIFY_SECRET if not present
+    #[arg(long)]
+    pub notify_secret: Option<String>,
+    /// Seconds to wait for each notification request, a notification never fails the sync
+    #[arg(long, default_value_t = 10)]
+    pub notify_timeout: u64,
+}
+
+#[derive(Parser)]
+pub struct KeepHistoryArgs {
+    /// Keep the old content of overwritten files in .thumper-history/<path>/<deploy-id>, to bring
+    /// it back with thumper restore
+    #[arg(long, default_value_t = false)]
+    pub history: bool,
+    /// Number of previous versions to keep of each file
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub history_keep: u64,
+    /// Remove previous versions older than this, like 90d, 12h or 2w
+    #[arg(long, value_parser = parse_age)]
+    pub history_max_age: Option<Duration>,
+}
+
+#[derive(Parser)]
+pub struct DeleteLimitArgs {
+    /// Abort before changing anything if more than this many files would be deleted
+    #[arg(long, default_value_t = 1000)]
+    pub max_delete: usize,
+    /// Abort before changing anything if more than this percentage of the remote files would be deleted,
+    /// once more than 10 files would be
+    #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub max_delete_percent: u8,
+    /// Delete as many files as needed, ignoring --max-delete and --max-delete-percent
+    #[arg(long, default_value_t = false)]
+    pub allow_mass_delete: bool,
+}
+
+#[derive(Parser)]
+pub struct CheckArgs {
+    /// Which bunny cdn endpoint to use
+    #[arg(short, long, default_value = "storage.bunnycdn.com")]
+    pub endpoint: String,
+    /// Password for the storage zone - looked up in environment variable THUMPER_KEY if not present
+    #[arg(short, long)]
+    pub access_key: Option<String>,
+    /// Local directory to compare with the storage zone
+    #[arg(name = "local_path", required = true, num_args = 1)]
+    pub local_path: String,
+    /// Which storage zone to compare with
+    #[arg(name = "storage_zone", required = true, num_args = 1)]
+    pub storage_zone: String,
+    /// Path inside the storage zone to compare with, path to a directory
+    #[arg(short, long, default_value = "/")]
+    pub path: String,
+    /// Filename of the lockfile, which is never reported as a pending deletion
+    #[arg(long, default_value = ".thumper.lock")]
+    pub lockfile: String,
+    /// Do not report deletions in the storage zone paths that start with this prefix (can pass multiple times)
+    #[arg(short, long)]
+    pub ignore: Vec<String>,
+    /// Number of threads to use when calling bunny.net API (default to number of cpus)
+    #[arg(short, long)]
+    pub concurrency: Option<usize>,
+    /// Compare the files in this list instead of everything in local_path, like for sync
+    #[arg(long)]
+    pub from_list: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct PlanArgs {
+    /// Which bunny cdn endpoint to use
+    #[arg(short, long, default_value = "storage.bunnycdn.com")]
+    pub endpoint: String,
+    /// Password for the storage zone - looked up in environment variable THUMPER_KEY if not present
+    #[arg(short, long)]
+    pub access_key: Option<String>,
+    /// Local directory to put in the storage zone
+    #[arg(name = "local_path", required = true, num_args = 1)]
+    pub local_path: String,
+    /// Which storage zone to sync to
+    #[arg(name = "storage_zone", required = true, num_args = 1)]
+    pub storage_zone: String,
+    /// Path inside the storage zone to sync to, path to a directory
+    #[arg(short, long, default_value = "/")]
+    pub path: String,
+    /// Filename to use for the lockfile when the plan is applied
+    #[arg(long, default_value = ".thumper.lock")]
+    pub lockfile: String,
+    /// Do not delete anything in the storage zone paths that start with this prefix (can pass multiple times)
+    #[arg(short, long)]
+    pub ignore: Vec<String>,
+    /// Number of threads to use when calling bunny.net API (default to number of cpus)
+    #[arg(short, long)]
+    pub concurrency: Option<usize>,
+    /// Plan the files in this list instead of everything in local_path, like for sync
+    #[arg(long)]
+    pub from_list: Option<String>,
+    /// File to save the plan to
+    #[arg(short, long, required = true)]
+    pub output: String,
+}
+
+#[derive(Parser)]
+pub struct ApplyArgs {
+    /// Plan file made by thumper plan
+    #[arg(name = "plan", required = true, num_args = 1)]
+    pub plan: String,
+    /// Password for the storage zone - looked up in environment variable THUMPER_KEY if not present
+    #[arg(short, long)]
+    pub access_key: Option<String>,
+    /// Don't sync, just verify the plan and show what would change
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Force a sync despite a hanging lock file
     #[arg(short, long, default_value_t = false)]
+    pub force: bool,
+    /// Set from the global --verbose
+    #[arg(skip)]
     pub verbose: bool,
     /// Number of threads to use when calling bunny.net API (default to number of cpus)
     #[arg(short, long)]
     pub concurrency: Option<usize>,
+    #[command(flatten)]
+    pub delete_limits: DeleteLimitArgs,
+    /// Move deleted files to .thumper-trash/<deploy-id>/ instead of deleting them, like for sync
+    #[arg(long, default_value_t = false)]
+    pub trash: bool,
+    #[command(flatten)]
+    pub history: KeepHistoryArgs,
+    /// Run every upload even when some fail, like for sync
+    #[arg(long, default_value_t = false)]
+    pub keep_going: bool,
+}
+
+#[derive(Parser)]
+pub struct PullArgs {
+    /// Which bunny cdn endpoint to use
+    #[arg(short, long, default_value = "storage.bunnycdn.com")]
+    pub endpoint: String,
+    /// Password for the storage zone - looked up in environment variable THUMPER_KEY if not present
+    #[arg(short, long)]
+    pub access_key: Option<String>,
+    /// Which storage zone to pull from
+    #[arg(name = "storage_zone", required = true, num_args = 1)]
+    pub storage_zone: String,
+    /// Local directory to place the files in, created if missing
+    #[arg(name = "local_path", required = true, num_args = 1)]
+    pub local_path: String,
+    /// Path inside the storage zone to pull from, path to a directory
+    #[arg(short, long, default_value = "/")]
+    pub path: String,
+    /// Don't download anything, just show what would change
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Delete local files that are not present in the storage zone
+    #[arg(long, default_value_t = false)]
+    pub delete: bool,
+    /// Do not pull or delete paths in the storage zone that start with this prefix (can pass multiple times)
+    #[arg(short, long)]
+    pub ignore: Vec<String>,
+    /// Set from the global --verbose
+    #[arg(skip)]
+    pub verbose: bool,
+    /// Number of threads to use when calling bunny.net API (default to number of cpus)
+    #[arg(short, long)]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Parser)]
+pub struct ZoneArgs {
+    /// Which bunny cdn endpoint to use
+    #[arg(short, long, default_value = "storage.bunnycdn.com")]
+    pub endpoint: String,
+    /// Password for the storage zone - looked up in environment variable THUMPER_KEY if not present
+    #[arg(short, long)]
+    pub access_key: Option<String>,
+    /// Which storage zone to use
+    #[arg(name = "storage_zone", required = true, num_args = 1)]
+    pub storage_zone: String,
+}
+
+#[derive(Subcommand)]
+pub enum TrashAction {
+    /// List the deploys in the trash and the files they deleted
+    List {
+        #[command(flatten)]
+        args: TrashListArgs,
+    },
+    /// Move the files of a deploy in the trash back to where they were deleted from
+    Restore {
+        #[command(flatten)]
+        args: TrashRestoreArgs,
+    },
+    /// Permanently delete deploys from the trash
+    Empty {
+        #[command(flatten)]
+        args: TrashEmptyArgs,
+    },
+}
+
+#[derive(Parser)]
+pub struct TrashListArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Only list the files of this deploy
+    #[arg(name = "deploy_id")]
+    pub deploy_id: Option<String>,
+    /// Print the listing as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+    /// Number of threads to use when calling bunny.net API (default to number of cpus)
+    #[arg(short, long)]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Parser)]
+pub struct TrashRestoreArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Deploy to restore files from, as shown by thumper trash list
+    #[arg(name = "deploy_id", required = true)]
+    pub deploy_id: String,
+    /// Only restore these files, or the files below these directories
+    #[arg(name = "paths")]
+    pub paths: Vec<String>,
+    /// Overwrite files that exist again since they were deleted
+    #[arg(short, long, default_value_t = false)]
+    pub force: bool,
+    /// Don't restore, just show what would be restored
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Print the restored files as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+    /// Number of threads to use when calling bunny.net API (default to number of cpus)
+    #[arg(short, long)]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Parser)]
+pub struct TrashEmptyArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Only delete deploys older than this, like 30d, 12h or 2w
+    #[arg(long, value_parser = parse_age)]
+    pub older_than: Option<Duration>,
+    /// Don't delete, just show what would be deleted
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Print the deleted deploys as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct PullZoneArgs {
+    /// Numeric ID of the pull zone serving the releases
+    #[arg(long, required = true)]
+    pub pull_zone: u64,
+    /// API key for bunny CDN -- looked up in environment variable THUMPER_API_KEY if not present
+    #[arg(long)]
+    pub api_key: Option<String>,
+    /// Base URL of the bunny.net API
+    #[arg(long, default_value = "https://api.bunny.net")]
+    pub api_endpoint: String,
+    /// Origin URL of the storage zone, the release path is appended to it
+    #[arg(long, required = true)]
+    pub origin: String,
+    /// Directory inside the storage zone to keep the releases in
+    #[arg(long, default_value = "releases/")]
+    pub releases_path: String,
+}
+
+#[derive(Parser)]
+pub struct ReleaseArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    #[command(flatten)]
+    pub pull_zone: PullZoneArgs,
+    /// Local directory to release
+    #[arg(name = "local_path", required = true, num_args = 1)]
+    pub local_path: String,
+    /// Number of releases to keep, including the new one and the one it replaces
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(2..))]
+    pub keep: u64,
+    /// Filename to use for the lockfile, placed in the releases path
+    #[arg(long, default_value = ".thumper.lock")]
+    pub lockfile: String,
+    /// Force a release despite a hanging lock file
+    #[arg(short, long, default_value_t = false)]
+    pub force: bool,
+    /// Don't upload or switch, just show what would be released
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Set from the global --verbose
+    #[arg(skip)]
+    pub verbose: bool,
+    /// Number of threads to use when calling bunny.net API (default to number of cpus)
+    #[arg(short, long)]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Parser)]
+pub struct RollbackArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    #[command(flatten)]
+    pub pull_zone: PullZoneArgs,
+    /// Release to switch to (default to the one before the current release)
+    #[arg(long)]
+    pub to: Option<String>,
+    /// Don't switch, just show which release would be switched to
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+#[derive(Subcommand)]
+pub enum PreviewsAction {
+    /// List the previews with their creation time, last deploy and git ref
+    List {
+        #[command(flatten)]
+        args: PreviewsListArgs,
+    },
+    /// Delete previews that were not deployed to for a while
+    Prune {
+        #[command(flatten)]
+        args: PreviewsPruneArgs,
+    },
+}
+
+#[derive(Parser)]
+pub struct PreviewsListArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Where previews are deployed in the storage zone, like for sync --preview-template
+    #[arg(long, default_value = "previews/{branch}/")]
+    pub template: String,
+    /// Print the previews as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct PreviewsPruneArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Where previews are deployed in the storage zone, like for sync --preview-template
+    #[arg(long, default_value = "previews/{branch}/")]
+    pub template: String,
+    /// Delete previews last deployed longer ago than this, like 7d, 12h or 2w
+    #[arg(long, required = true, value_parser = parse_age)]
+    pub older_than: Duration,
+    /// Never delete these previews (comma separated or passed multiple times)
+    #[arg(long, value_delimiter = ',')]
+    pub keep: Vec<String>,
+    /// Don't delete, just show what would be deleted
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Print the deleted previews as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct HistoryArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Path of the file inside the storage zone
+    #[arg(name = "path", required = true)]
+    pub path: String,
+    /// Print the versions as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct RestoreArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Path of the file inside the storage zone
+    #[arg(name = "path", required = true)]
+    pub path: String,
+    /// Version to restore, as shown by thumper history (default to the most recent one)
+    #[arg(long)]
+    pub version: Option<String>,
+    /// Don't restore, just show what would be restored
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Print the restored file as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct LsArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Path inside the storage zone to list
+    #[arg(name = "path", default_value = "/")]
+    pub path: String,
+    /// List all files in the subtree
+    #[arg(short, long, default_value_t = false)]
+    pub recursive: bool,
+    /// Show size, last change and checksum
+    #[arg(short, long, default_value_t = false)]
+    pub long: bool,
+    /// Print the listing as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+    /// Number of threads to use when listing recursively (default to number of cpus)
+    #[arg(short, long)]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Parser)]
+pub struct CatArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Path of the file inside the storage zone
+    #[arg(name = "path", required = true, num_args = 1)]
+    pub path: String,
+    /// Print the file as JSON with size and checksum
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct PutArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Local file to upload
+    #[arg(name = "local", required = true, num_args = 1)]
+    pub local: String,
+    /// Path inside the storage zone, the file name of local is kept if this ends with /
+    #[arg(name = "remote", required = true, num_args = 1)]
+    pub remote: String,
+    /// Content type to use instead of detecting it from the file
+    #[arg(long)]
+    pub content_type: Option<String>,
+    /// Don't upload, just show what would change
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Print the changes as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct RmArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Path inside the storage zone to delete
+    #[arg(name = "path", required = true, num_args = 1)]
+    pub path: String,
+    /// Delete a directory and everything in it
+    #[arg(short, long, default_value_t = false)]
+    pub recursive: bool,
+    /// Don't delete, just show what would change
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Print the changes as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+    /// Number of threads to use when listing recursively (default to number of cpus)
+    #[arg(short, long)]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Parser)]
+pub struct MvArgs {
+    #[command(flatten)]
+    pub zone: ZoneArgs,
+    /// Path of the file to move
+    #[arg(name = "source", required = true, num_args = 1)]
+    pub source: String,
+    /// New path of the file, the file name is kept if this ends with /
+    #[arg(name = "destination", required = true, num_args = 1)]
+    pub destination: String,
+    /// Don't move, just show what would change
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Print the changes as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
 }
 
 #[cfg(test)]
@@ -0,0 +1,159 @@
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+use std::path::Path;
+
+const TEXT_EXTENSIONS: &[&str] = &[
+    "html",
+    "htm",
+    "css",
+    "js",
+    "mjs",
+    "cjs",
+    "json",
+    "map",
+    "xml",
+    "svg",
+    "txt",
+    "md",
+    "csv",
+    "yml",
+    "yaml",
+    "toml",
+    "webmanifest",
+    "rss",
+    "atom",
+];
+
+const TEXT_CONTENT_TYPES: &[&str] = &[
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "image/svg+xml",
+];
+
+fn is_text(remote: &str, mime_type: Option<&str>) -> bool {
+    let by_content_type = mime_type
+        .map(|mime| mime.starts_with("text/") || TEXT_CONTENT_TYPES.contains(&mime))
+        .unwrap_or(false);
+    let by_extension = Path::new(remote)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| TEXT_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        .unwrap_or(false);
+    by_content_type || by_extension
+}
+
+fn short_hash(checksum: &[u8]) -> String {
+    hex::encode(&checksum[..6])
+}
+
+fn summary(old: &[u8], new: &[u8]) -> String {
+    format!(
+        "{} -> {} bytes, sha256 {} -> {}\n",
+        old.len(),
+        new.len(),
+        short_hash(&Sha256::digest(old)),
+        short_hash(&Sha256::digest(new))
+    )
+}
+
+/// Whether replacing the `old_len` bytes of `remote` with `new_len` bytes is worth downloading
+/// the old content for a unified diff, which only text files up to `max_bytes` get. A remote file
+/// of unknown size is never downloaded.
+pub fn is_diffable(
+    remote: &str,
+    mime_type: Option<&str>,
+    old_len: Option<u64>,
+    new_len: usize,
+    max_bytes: usize,
+) -> bool {
+    is_text(remote, mime_type)
+        && new_len <= max_bytes
+        && old_len.is_some_and(|old_len| old_len <= max_bytes as u64)
+}
+
+/// Describe a change that is not diffed, with the checksum of the old content from the listing
+pub fn summarize_change(old_checksum: Option<&[u8; 32]>, new: &[u8]) -> String {
+    let old = old_checksum.map_or("unknown".to_string(), |checksum| short_hash(checksum));
+    format!(
+        "{} bytes, sha256 {old} -> {}\n",
+        new.len(),
+        short_hash(&Sha256::digest(new))
+    )
+}
+
+/// Describe how `remote` changes when `old` is replaced by `new`.
+///
+/// Text files up to `max_bytes` get a unified diff, everything else gets a size and hash summary.
+pub fn describe_change(
+    remote: &str,
+    mime_type: Option<&str>,
+    old: &[u8],
+    new: &[u8],
+    max_bytes: usize,
+) -> String {
+    if !is_text(remote, mime_type) || old.len() > max_bytes || new.len() > max_bytes {
+        return summary(old, new);
+    }
+    match (std::str::from_utf8(old), std::str::from_utf8(new)) {
+        (Ok(old), Ok(new)) => TextDiff::from_lines(old, new)
+            .unified_diff()
+            .header(&format!("a/{remote}"), &format!("b/{remote}"))
+            .to_string(),
+        _ => summary(old, new),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_text_files() {
+        let diff = describe_change(
+            "docs/index.html",
+            None,
+            b"<h1>Hello</h1>\n<p>old</p>\n",
+            b"<h1>Hello</h1>\n<p>new</p>\n",
+            1024,
+        );
+        assert!(diff.starts_with("--- a/docs/index.html\n+++ b/docs/index.html\n"));
+        assert!(diff.contains("-<p>old</p>\n+<p>new</p>\n"));
+    }
+
+    #[test]
+    fn summarizes_binary_and_large_files() {
+        let binary = describe_change("logo.png", Some("image/png"), b"old", b"newer", 1024);
+        assert!(binary.starts_with("3 -> 5 bytes, sha256 "));
+        let large = describe_change("big.txt", Some("text/plain"), b"old", b"newer", 4);
+        assert!(large.starts_with("3 -> 5 bytes"));
+        let by_type = describe_change("data", Some("application/json"), b"{}\n", b"[]\n", 1024);
+        assert!(by_type.contains("-{}\n+[]\n"));
+    }
+
+    #[test]
+    fn diffs_only_small_text_files() {
+        assert!(is_diffable("index.html", None, Some(20), 10, 1024));
+        assert!(!is_diffable("index.html", None, Some(20), 2048, 1024));
+        assert!(!is_diffable("index.html", None, Some(1 << 30), 10, 1024));
+        assert!(!is_diffable("index.html", None, None, 10, 1024));
+        assert!(!is_diffable(
+            "logo.png",
+            Some("image/png"),
+            Some(20),
+            10,
+            1024
+        ));
+        let old: [u8; 32] = Sha256::digest(b"old").into();
+        let summary = summarize_change(Some(&old), b"newer");
+        assert_eq!(
+            summary,
+            format!(
+                "5 bytes, sha256 {} -> {}\n",
+                &hex::encode(old)[..12],
+                &hex::encode(Sha256::digest(b"newer"))[..12]
+            )
+        );
+        assert!(summarize_change(None, b"newer").starts_with("5 bytes, sha256 unknown -> "));
+    }
+}
@@ -0,0 +1,255 @@
+use clap::ValueEnum;
+use reqwest::StatusCode;
+use reqwest::blocking::Response;
+use serde::Serialize;
+
+/// The failures of thumper that scripts can tell apart, each with its own exit code
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{lockfile} is locked since {since}, pass --force to sync anyway")]
+    LockHeld { lockfile: String, since: String },
+    #[error("Access denied to {path} ({status}), check the access key")]
+    Unauthorized { path: String, status: StatusCode },
+    #[error("{path} does not exist")]
+    NotFound { path: String },
+    #[error("Request for {path} failed with {status}")]
+    Http { path: String, status: StatusCode },
+    #[error("Unable to reach bunny.net for {path}")]
+    Network {
+        path: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Invalid response for {path}")]
+    InvalidResponse {
+        path: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Checksum mismatch after {after} {path}")]
+    ChecksumMismatch { path: String, after: &'static str },
+    #[error("Unable to list {}", unlisted.join(", "))]
+    IncompleteListing { unlisted: Vec<String> },
+    #[error("{0}")]
+    SafetyLimit(String),
+    #[error("{message}")]
+    SyncFailed {
+        message: String,
+        failures: Vec<Failure>,
+    },
+}
+
+/// A job that failed during a sync
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    pub path: String,
+    pub kind: String,
+    pub error: String,
+}
+
+impl Error {
+    /// Turn an unsuccessful `response` to a request for `path` into an error
+    pub fn check(response: Response, path: &str) -> Result<Response, Error> {
+        let status = response.status();
+        let path = path.to_string();
+        match status {
+            _ if status.is_success() => Ok(response),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Err(Error::Unauthorized { path, status })
+            }
+            StatusCode::NOT_FOUND => Err(Error::NotFound { path }),
+            _ => Err(Error::Http { path, status }),
+        }
+    }
+
+    /// Short name of the kind of error, stable for scripts
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::LockHeld { .. } => "lock_held",
+            Error::Unauthorized { .. } => "unauthorized",
+            Error::NotFound { .. } => "not_found",
+            Error::Http { .. } => "http",
+            Error::Network { .. } => "network",
+            Error::InvalidResponse { .. } => "invalid_response",
+            Error::ChecksumMismatch { .. } => "checksum_mismatch",
+            Error::IncompleteListing { .. } => "incomplete_listing",
+            Error::SafetyLimit(_) => "safety_limit",
+            Error::SyncFailed { .. } => "sync_failed",
+        }
+    }
+
+    /// The exit code of thumper when it fails with this error, documented in the README
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::LockHeld { .. } => 10,
+            Error::Unauthorized { .. } => 11,
+            Error::NotFound { .. } => 12,
+            Error::Http { .. } => 13,
+            Error::Network { .. } => 14,
+            Error::InvalidResponse { .. } => 15,
+            Error::ChecksumMismatch { .. } => 16,
+            Error::IncompleteListing { .. } => 17,
+            Error::SafetyLimit(_) => 18,
+            Error::SyncFailed { .. } => 19,
+        }
+    }
+
+    fn path(&self) -> Option<&str> {
+        match self {
+            Error::LockHeld { lockfile: path, .. }
+            | Error::Unauthorized { path, .. }
+            | Error::NotFound { path }
+            | Error::Http { path, .. }
+            | Error::Network { path, .. }
+            | Error::InvalidResponse { path, .. }
+            | Error::ChecksumMismatch { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The HTTP status bunny.net answered with, if it answered
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Unauthorized { status, .. } | Error::Http { status, .. } => Some(*status),
+            Error::NotFound { .. } => Some(StatusCode::NOT_FOUND),
+            _ => None,
+        }
+    }
+}
+
+/// The typed error somewhere in the chain of `e`, if there is one
+pub fn find(e: &anyhow::Error) -> Option<&Error> {
+    e.chain().find_map(|cause| cause.downcast_ref::<Error>())
+}
+
+/// Exit code for errors that are not an `Error`
+const OTHER_ERROR: i32 = 1;
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum ErrorFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl ErrorFormat {
+    /// The `--error-format` among command line `args`, for reporting that they could not be parsed
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut format = ErrorFormat::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = match arg.strip_prefix("--error-format") {
+                Some("") => args.next(),
+                Some(value) => value.strip_prefix('=').map(str::to_string),
+                None => None,
+            };
+            if let Some(value) = value
+                && let Ok(value) = ErrorFormat::from_str(value.as_str(), false)
+            {
+                format = value;
+            }
+        }
+        format
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    error: &'static str,
+    exit_code: i32,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "<[Failure]>::is_empty")]
+    failures: &'a [Failure],
+}
+
+fn report_of(e: &anyhow::Error) -> Report<'_> {
+    let typed = find(e);
+    Report {
+        error: typed.map(Error::code).unwrap_or("error"),
+        exit_code: typed.map(Error::exit_code).unwrap_or(OTHER_ERROR),
+        message: format!("{e:#}"),
+        path: typed.and_then(Error::path),
+        status: typed.and_then(Error::status).map(|status| status.as_u16()),
+        failures: match typed {
+            Some(Error::SyncFailed { failures, .. }) => failures,
+            _ => &[],
+        },
+    }
+}
+
+/// The error for invalid command line arguments, without the `error: ` clap starts it with
+pub fn usage(e: &clap::Error) -> anyhow::Error {
+    let message = e.render().to_string();
+    anyhow::anyhow!("{}", message.trim_start_matches("error: ").trim_end())
+}
+
+/// Print `e` to stderr in `format`, returning the exit code for it
+pub fn report(e: &anyhow::Error, format: ErrorFormat) -> i32 {
+    let report = report_of(e);
+    match format {
+        ErrorFormat::Text => eprintln!("Error: {e:?}"),
+        ErrorFormat::Json => match serde_json::to_string(&report) {
+            Ok(json) => eprintln!("{json}"),
+            Err(_) => eprintln!("Error: {e:?}"),
+        },
+    }
+    report.exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use anyhow::Context;
+    use clap::Parser;
+
+    #[test]
+    fn reports_typed_errors_through_context() {
+        let e = Err::<(), _>(Error::Http {
+            path: "site/index.html".into(),
+            status: StatusCode::SERVICE_UNAVAILABLE,
+        })
+        .context("Unable to sync")
+        .unwrap_err();
+        let report = serde_json::to_value(report_of(&e)).unwrap();
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "error": "http",
+                "exit_code": 13,
+                "message": "Unable to sync: Request for site/index.html failed with 503 Service Unavailable",
+                "path": "site/index.html",
+                "status": 503,
+            })
+        );
+
+        let other = anyhow::anyhow!("Something else");
+        assert_eq!(report_of(&other).exit_code, OTHER_ERROR);
+        assert_eq!(report_of(&other).error, "error");
+    }
+
+    #[test]
+    fn reports_invalid_arguments_in_the_requested_format() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let json = ErrorFormat::from_args(args(&["thumper", "check", "--error-format", "json"]));
+        assert!(matches!(json, ErrorFormat::Json));
+        let json = ErrorFormat::from_args(args(&["thumper", "--error-format=json", "sync"]));
+        assert!(matches!(json, ErrorFormat::Json));
+        let text = ErrorFormat::from_args(args(&["thumper", "--error-format", "yaml"]));
+        assert!(matches!(text, ErrorFormat::Text));
+
+        let parsed = Cli::try_parse_from(["thumper", "check"]);
+        let e = usage(&parsed.err().unwrap());
+        let report = report_of(&e);
+        assert_eq!((report.error, report.exit_code), ("error", OTHER_ERROR));
+        assert!(
+            report
+                .message
+                .starts_with("the following required arguments")
+        );
+    }
+}
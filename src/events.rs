@@ -0,0 +1,309 @@
+use crate::planning::Reason;
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Version of the JSON Lines schema of `--output jsonl`. Adding events or fields keeps the
+/// version, renaming or removing them bumps it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Something that happened during a sync
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    LockAcquired {
+        lockfile: &'a str,
+    },
+    LockReleased {
+        lockfile: &'a str,
+    },
+    /// A directory of the storage zone was listed
+    Listed {
+        path: &'a str,
+        files: usize,
+        directories: usize,
+    },
+    /// Listing a directory failed and is tried again
+    ListRetry {
+        path: &'a str,
+        attempt: u32,
+        error: String,
+    },
+    /// Listing a directory failed for good, so the listing is incomplete
+    ListFailed {
+        path: &'a str,
+        error: String,
+    },
+    /// A request to the storage zone completed, `status` is null when bunny.net could not be
+    /// reached. `operation` is one of list, get, put or delete
+    Request {
+        operation: &'a str,
+        path: &'a str,
+        status: Option<u16>,
+        duration_ms: u64,
+        retries: u32,
+    },
+    /// Every directory under `path` was listed, or failed to list
+    ListingDone {
+        path: &'a str,
+        files: usize,
+        duration_ms: u64,
+    },
+    /// The jobs of the sync are known, `bytes` is the size of the local files to upload or compare
+    PlanReady {
+        new_files: usize,
+        existing_files: usize,
+        deletes: usize,
+        kept: usize,
+        empty_directories: usize,
+        bytes: u64,
+    },
+    /// A job completed, `action` is one of put, unchanged, keep, trash or delete. `bytes` were
+    /// uploaded, `size` is the size of the local file whether it was uploaded or not. `hash_ms`
+    /// of `duration_ms` went to reading and hashing the local file.
+    Job {
+        path: &'a str,
+        action: &'a str,
+        reason: Reason,
+        bytes: u64,
+        size: u64,
+        hash_ms: u64,
+        duration_ms: u64,
+        dry_run: bool,
+    },
+    JobFailed {
+        path: &'a str,
+        error: String,
+    },
+    /// All jobs ran
+    Summary {
+        completed: usize,
+        failed: usize,
+        bytes: u64,
+        duration_ms: u64,
+    },
+}
+
+/// Receives every event of a sync
+pub trait Observer: Send + Sync {
+    fn observe(&self, event: &Event);
+}
+
+/// Lets the caller keep a handle on an observer to read what it gathered
+impl<T: Observer> Observer for Arc<T> {
+    fn observe(&self, event: &Event) {
+        self.as_ref().observe(event);
+    }
+}
+
+/// Hands events to any number of observers
+#[derive(Clone, Default)]
+pub struct Events {
+    observers: Arc<Vec<Box<dyn Observer>>>,
+}
+
+impl Events {
+    pub fn new(observers: Vec<Box<dyn Observer>>) -> Self {
+        Events {
+            observers: Arc::new(observers),
+        }
+    }
+
+    /// Events that are printed to stdout in `output`
+    pub fn printing(output: Output, verbose: bool) -> Self {
+        Events::new(vec![output.observer(verbose)])
+    }
+
+    pub fn emit(&self, event: Event) {
+        for observer in self.observers.iter() {
+            observer.observe(&event);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum Output {
+    /// Lines like `index.html: put (new file)`, with --verbose or --dry-run
+    #[default]
+    Text,
+    /// One JSON object per event
+    Jsonl,
+}
+
+impl Output {
+    /// The observer that prints events to stdout in this format
+    pub fn observer(self, verbose: bool) -> Box<dyn Observer> {
+        match self {
+            Output::Text => Box::new(Text { verbose }),
+            Output::Jsonl => Box::new(JsonLines),
+        }
+    }
+}
+
+/// Prints a line for each job when `verbose`
+struct Text {
+    verbose: bool,
+}
+
+impl Observer for Text {
+    fn observe(&self, event: &Event) {
+        if let Event::Job {
+            path,
+            action,
+            reason,
+            ..
+        } = event
+            && self.verbose
+        {
+            println!("{path}: {action} ({reason})");
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    schema: u32,
+    time: String,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Prints every event as a line of JSON
+struct JsonLines;
+
+fn json_line(event: &Event) -> serde_json::Result<String> {
+    serde_json::to_string(&Line {
+        schema: SCHEMA_VERSION,
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        event,
+    })
+}
+
+impl Observer for JsonLines {
+    fn observe(&self, event: &Event) {
+        if let Ok(line) = json_line(event) {
+            println!("{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planning::SyncPlan;
+    use crate::testing::{FakeStorageZone, TempDir};
+    use crate::{ExecuteOptions, execute_sync};
+    use std::fs;
+    use std::sync::Mutex;
+
+    /// Keeps the name of each event with its path or action
+    #[derive(Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Observer for Recorder {
+        fn observe(&self, event: &Event) {
+            let line = match event {
+                Event::Request {
+                    operation, path, ..
+                } => format!("{operation} request {path}"),
+                Event::Listed { path, files, .. } => format!("listed {path} {files}"),
+                Event::ListingDone { path, files, .. } => format!("listing done {path} {files}"),
+                Event::Job {
+                    path,
+                    action,
+                    bytes,
+                    ..
+                } => format!("{action} {path} {bytes}"),
+                Event::Summary {
+                    completed, bytes, ..
+                } => format!("summary {completed} {bytes}"),
+                event => format!("{event:?}"),
+            };
+            self.0.lock().unwrap().push(line);
+        }
+    }
+
+    #[test]
+    fn emits_listing_jobs_and_summary() {
+        let zone = FakeStorageZone::start("events-zone");
+        zone.insert("site/old.html", b"old");
+        let recorded = Arc::new(Mutex::new(vec![]));
+        let events = Events::new(vec![Box::new(Recorder(recorded.clone()))]);
+        let client = zone.client().with_events(events);
+        let dir = TempDir::new("events");
+        let local = dir.join("site.css");
+        fs::write(&local, "body {}").unwrap();
+
+        client.list_files("site/", &[], 1).unwrap();
+        let job = vec![
+            SyncPlan::Put {
+                local: local.clone().into(),
+                remote: "site/site.css".into(),
+            },
+            SyncPlan::Delete {
+                remote: "site/old.html".into(),
+            },
+        ];
+        execute_sync(ExecuteOptions::default(), job, &client, ".thumper.lock", 1).unwrap();
+        // Workers send requests while the main thread reports the jobs, so only the order within
+        // each is fixed
+        let (requests, rest): (Vec<_>, Vec<_>) = recorded
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .partition(|line| line.contains(" request "));
+        assert_eq!(
+            requests,
+            vec![
+                "list request site/",
+                "put request site/site.css",
+                "delete request site/old.html"
+            ]
+        );
+        assert_eq!(
+            rest,
+            vec![
+                "listed site/ 1",
+                "listing done site/ 1",
+                "put site/site.css 7",
+                "delete site/old.html 0",
+                "summary 2 7"
+            ]
+        );
+    }
+
+    #[test]
+    fn serializes_versioned_lines() {
+        let line = json_line(&Event::Job {
+            path: "index.html",
+            action: "put",
+            reason: Reason::NewFile,
+            bytes: 13,
+            size: 13,
+            hash_ms: 1,
+            duration_ms: 4,
+            dry_run: false,
+        })
+        .unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert!(value["time"].as_str().unwrap().ends_with('Z'));
+        value.as_object_mut().unwrap().remove("time");
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "schema": 1,
+                "event": "job",
+                "path": "index.html",
+                "action": "put",
+                "reason": "new_file",
+                "bytes": 13,
+                "size": 13,
+                "hash_ms": 1,
+                "duration_ms": 4,
+                "dry_run": false,
+            })
+        );
+    }
+}
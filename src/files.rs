@@ -0,0 +1,469 @@
+use crate::api::{FileInfo, StorageZoneClient};
+use crate::cli::{CatArgs, LsArgs, MvArgs, PutArgs, RmArgs, ZoneArgs};
+use crate::error::Error;
+use crate::{init_client, normalize_path, with_reserved_prefixes};
+use anyhow::{Context, anyhow};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::Path;
+use std::{fs, io};
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    path: String,
+    is_directory: bool,
+    size: u64,
+    last_changed: String,
+    checksum: Option<String>,
+    content_type: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Change {
+    pub path: String,
+    pub action: &'static str,
+    pub dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct Content<'a> {
+    path: &'a str,
+    size: usize,
+    sha256: String,
+    content: Option<&'a str>,
+}
+
+pub fn client_for(zone: ZoneArgs) -> anyhow::Result<StorageZoneClient> {
+    let ZoneArgs {
+        endpoint,
+        access_key,
+        storage_zone,
+    } = zone;
+    init_client(access_key, storage_zone, endpoint)
+}
+
+fn entry(client: &StorageZoneClient, file: FileInfo) -> Entry {
+    Entry {
+        path: client.name_of(&file),
+        is_directory: file.is_directory,
+        size: file.length,
+        last_changed: file.last_changed,
+        checksum: file.checksum,
+        content_type: file.content_type,
+    }
+}
+
+/// Paths without a file name, like `docs/` or `/`, get the file name of `source` appended
+fn destination_for(destination: &str, source: &str) -> anyhow::Result<String> {
+    let destination = destination.trim_start_matches('/');
+    if destination.is_empty() || destination.ends_with('/') {
+        let name = Path::new(source)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("Unable to tell file name of {source}"))?;
+        Ok(format!("{destination}{name}"))
+    } else {
+        Ok(destination.to_string())
+    }
+}
+
+pub fn print_changes(changes: &[Change], json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string(changes)?);
+    } else {
+        for Change { path, action, .. } in changes {
+            println!("{path}: {action}");
+        }
+    }
+    Ok(())
+}
+
+fn ls(
+    client: &StorageZoneClient,
+    path: &str,
+    recursive: bool,
+    concurrency: usize,
+) -> anyhow::Result<Vec<Entry>> {
+    // bunny.net only lists directories given with a trailing /
+    let path = normalize_path(path.to_string());
+    let path = path.trim_start_matches('/');
+    let files = if recursive {
+        // Trash and history are left out like sync leaves them out, unless they are listed
+        let skip: Vec<String> = with_reserved_prefixes(vec![])
+            .into_iter()
+            .filter(|reserved| !path.starts_with(reserved.as_str()))
+            .collect();
+        client
+            .concurrent_discover_files(path, &skip, concurrency)?
+            .into_iter()
+            .filter(|file| !file.is_directory)
+            .collect()
+    } else {
+        client.ls_dir(path)?
+    };
+    let mut entries: Vec<_> = files.into_iter().map(|file| entry(client, file)).collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+pub fn do_ls(args: LsArgs) -> anyhow::Result<()> {
+    let LsArgs {
+        zone,
+        path,
+        recursive,
+        long,
+        json,
+        concurrency,
+    } = args;
+    let client = client_for(zone)?;
+    let entries = ls(
+        &client,
+        path.as_str(),
+        recursive,
+        concurrency.unwrap_or_else(num_cpus::get),
+    )?;
+    if json {
+        println!("{}", serde_json::to_string(&entries)?);
+    } else {
+        for entry in entries {
+            if long {
+                println!(
+                    "{:>12} {:<23} {:<64} {}",
+                    entry.size,
+                    entry.last_changed,
+                    entry.checksum.as_deref().unwrap_or("-"),
+                    entry.path
+                );
+            } else {
+                println!("{}", entry.path);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn do_cat(args: CatArgs) -> anyhow::Result<()> {
+    let CatArgs { zone, path, json } = args;
+    let client = client_for(zone)?;
+    let path = path.trim_start_matches('/');
+    let content = client.download_file(path)?;
+    if json {
+        let content = Content {
+            path,
+            size: content.len(),
+            sha256: hex::encode_upper(Sha256::digest(&content)),
+            content: std::str::from_utf8(&content).ok(),
+        };
+        println!("{}", serde_json::to_string(&content)?);
+    } else {
+        io::stdout().write_all(&content)?;
+    }
+    Ok(())
+}
+
+fn put(
+    client: &StorageZoneClient,
+    local: &str,
+    remote: &str,
+    content_type: Option<String>,
+    dry_run: bool,
+) -> anyhow::Result<Change> {
+    let remote = destination_for(remote, local)?;
+    let content = fs::read(local).with_context(|| format!("Unable to read {local}"))?;
+    let content_type = match content_type {
+        Some(content_type) => Some(content_type),
+        None => infer::get_from_path(local)?.map(|t| t.mime_type().to_string()),
+    };
+    if !dry_run {
+        client.put_file(remote.as_str(), content, content_type.as_deref())?;
+    }
+    Ok(Change {
+        path: remote,
+        action: "put",
+        dry_run,
+    })
+}
+
+pub fn do_put(args: PutArgs) -> anyhow::Result<()> {
+    let PutArgs {
+        zone,
+        local,
+        remote,
+        content_type,
+        dry_run,
+        json,
+    } = args;
+    let client = client_for(zone)?;
+    let change = put(&client, &local, &remote, content_type, dry_run)?;
+    print_changes(&[change], json)
+}
+
+fn rm(
+    client: &StorageZoneClient,
+    path: &str,
+    recursive: bool,
+    concurrency: usize,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Change>> {
+    let path = path.trim_start_matches('/');
+    let found = client.stat(path)?.ok_or_else(|| Error::NotFound {
+        path: path.to_string(),
+    })?;
+    if !found.is_directory {
+        if !dry_run {
+            client.delete_file(path)?;
+        }
+        return Ok(vec![Change {
+            path: path.to_string(),
+            action: "delete",
+            dry_run,
+        }]);
+    }
+    if !recursive {
+        return Err(anyhow!("{path} is a directory, use -r to delete it"));
+    }
+
+    let directory = format!("{}/", path.trim_end_matches('/'));
+    let mut changes: Vec<_> = client
+        .concurrent_discover_files(directory.as_str(), &[], concurrency)?
+        .into_iter()
+        .filter(|file| !file.is_directory)
+        .map(|file| Change {
+            path: client.name_of(&file),
+            action: "delete",
+            dry_run,
+        })
+        .collect();
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    if !dry_run {
+        client.delete_file(directory.as_str())?;
+    }
+    changes.push(Change {
+        path: directory,
+        action: "delete",
+        dry_run,
+    });
+    Ok(changes)
+}
+
+pub fn do_rm(args: RmArgs) -> anyhow::Result<()> {
+    let RmArgs {
+        zone,
+        path,
+        recursive,
+        dry_run,
+        json,
+        concurrency,
+    } = args;
+    let client = client_for(zone)?;
+    let changes = rm(
+        &client,
+        path.as_str(),
+        recursive,
+        concurrency.unwrap_or_else(num_cpus::get),
+        dry_run,
+    )?;
+    print_changes(&changes, json)
+}
+
+fn mv(
+    client: &StorageZoneClient,
+    source: &str,
+    destination: &str,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Change>> {
+    let source = source.trim_start_matches('/');
+    let destination = destination_for(destination, source)?;
+    if destination == source {
+        return Err(anyhow!("{source} is already at {destination}"));
+    }
+    if dry_run {
+        match client.stat(source)? {
+            Some(file) if !file.is_directory => {}
+            _ => {
+                return Err(Error::NotFound {
+                    path: source.to_string(),
+                }
+                .into());
+            }
+        }
+    } else {
+        client.move_file(source, destination.as_str())?;
+    }
+    Ok(vec![
+        Change {
+            path: destination,
+            action: "put",
+            dry_run,
+        },
+        Change {
+            path: source.to_string(),
+            action: "delete",
+            dry_run,
+        },
+    ])
+}
+
+pub fn do_mv(args: MvArgs) -> anyhow::Result<()> {
+    let MvArgs {
+        zone,
+        source,
+        destination,
+        dry_run,
+        json,
+    } = args;
+    let client = client_for(zone)?;
+    let changes = mv(&client, &source, &destination, dry_run)?;
+    print_changes(&changes, json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeStorageZone, TempDir};
+
+    #[test]
+    fn destination_appends_file_name_to_directories() {
+        assert_eq!(destination_for("/", "dist/a.js").unwrap(), "a.js");
+        assert_eq!(destination_for("js/", "dist/a.js").unwrap(), "js/a.js");
+        assert_eq!(destination_for("/js/b.js", "dist/a.js").unwrap(), "js/b.js");
+    }
+
+    #[test]
+    fn ls_lists_directory_or_tree() {
+        let zone = FakeStorageZone::start("ls-zone");
+        zone.insert("index.html", b"hello");
+        zone.insert("css/site.css", b"body {}");
+        zone.insert(".thumper-history/index.html/20250415T165233Z", b"old");
+        zone.insert(".thumper-trash/20250415T165233Z/old.html", b"old");
+        let client = zone.client();
+
+        let top: Vec<_> = ls(&client, "/", false, 2)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.path, entry.size))
+            .collect();
+        assert_eq!(
+            top,
+            vec![
+                (".thumper-history/".to_string(), 0),
+                (".thumper-trash/".to_string(), 0),
+                ("css/".to_string(), 0),
+                ("index.html".to_string(), 5)
+            ]
+        );
+
+        let tree: Vec<_> = ls(&client, "/", true, 2)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert_eq!(tree, vec!["css/site.css", "index.html"]);
+        let css: Vec<_> = ls(&client, "css", false, 2)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert_eq!(css, vec!["css/site.css"]);
+        let history: Vec<_> = ls(&client, ".thumper-history/", true, 2)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert_eq!(
+            history,
+            vec![".thumper-history/index.html/20250415T165233Z"]
+        );
+    }
+
+    #[test]
+    fn put_uploads_and_respects_dry_run() {
+        let zone = FakeStorageZone::start("put-zone");
+        let client = zone.client();
+        let dir = TempDir::new("put");
+        let local = dir.join("put.txt");
+        fs::write(&local, "content").unwrap();
+        let local = local.to_str().unwrap();
+
+        put(&client, local, "dry/", None, true).unwrap();
+        assert!(zone.files().is_empty());
+
+        let change = put(&client, local, "docs/", Some("text/plain".into()), false).unwrap();
+        assert_eq!(zone.get(change.path.as_str()), Some(b"content".to_vec()));
+        assert_eq!(
+            zone.content_type(change.path.as_str()).as_deref(),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn rm_requires_recursive_for_directories() {
+        let zone = FakeStorageZone::start("rm-zone");
+        zone.insert("keep.txt", b"keep");
+        zone.insert("docs/a.txt", b"a");
+        zone.insert("docs/sub/b.txt", b"b");
+        let client = zone.client();
+
+        assert!(rm(&client, "docs", false, 2, false).is_err());
+        assert!(rm(&client, "missing.txt", false, 2, false).is_err());
+
+        let planned = rm(&client, "docs", true, 2, true).unwrap();
+        assert_eq!(planned.len(), 3);
+        assert_eq!(zone.files().len(), 3);
+
+        rm(&client, "docs", true, 2, false).unwrap();
+        assert_eq!(zone.files(), vec!["keep.txt"]);
+
+        rm(&client, "/keep.txt", false, 2, false).unwrap();
+        assert!(zone.files().is_empty());
+    }
+
+    #[test]
+    fn mv_moves_file() {
+        let zone = FakeStorageZone::start("mv-zone");
+        zone.insert("old/name.txt", b"content");
+        let client = zone.client();
+
+        mv(&client, "old/name.txt", "new/", true).unwrap();
+        assert_eq!(zone.files(), vec!["old/name.txt"]);
+
+        let changes = mv(&client, "old/name.txt", "new/", false).unwrap();
+        assert_eq!(changes[0].path, "new/name.txt");
+        assert_eq!(zone.files(), vec!["new/name.txt"]);
+        assert_eq!(zone.get("new/name.txt"), Some(b"content".to_vec()));
+        assert!(mv(&client, "old/name.txt", "new/", false).is_err());
+    }
+
+    #[test]
+    fn missing_paths_exit_as_not_found() {
+        let zone = FakeStorageZone::start("missing-zone");
+        let client = zone.client();
+        let exit_code = |e: anyhow::Error| crate::error::find(&e).map(Error::exit_code);
+
+        let cat = client.download_file("missing.txt").unwrap_err();
+        assert_eq!(cat.exit_code(), 12);
+        assert_eq!(
+            exit_code(rm(&client, "missing.txt", false, 2, false).unwrap_err()),
+            Some(12)
+        );
+        assert_eq!(
+            exit_code(mv(&client, "missing.txt", "new/", true).unwrap_err()),
+            Some(12)
+        );
+    }
+
+    #[test]
+    fn mv_refuses_to_move_file_onto_itself() {
+        let zone = FakeStorageZone::start("mv-self-zone");
+        zone.insert("a.txt", b"a");
+        zone.insert("docs/b.txt", b"b");
+        let client = zone.client();
+
+        assert!(mv(&client, "a.txt", "a.txt", false).is_err());
+        assert!(mv(&client, "a.txt", "/", false).is_err());
+        assert!(mv(&client, "docs/b.txt", "docs/", false).is_err());
+        assert!(client.move_file("/a.txt", "a.txt").is_err());
+        assert_eq!(zone.files(), vec!["a.txt", "docs/b.txt"]);
+    }
+}
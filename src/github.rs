@@ -0,0 +1,245 @@
+use crate::error::{self, Error};
+use crate::events::{Event, Observer};
+use crate::progress::format_bytes;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::{env, io};
+
+/// Rows of the changed files table in the step summary, which GitHub limits to 1 MiB
+const MAX_ROWS: usize = 500;
+
+/// Reports a sync to GitHub Actions: annotations for failures, a Markdown step summary and step
+/// outputs like `changed_count`
+pub struct GithubActions {
+    title: String,
+    step_summary: Option<PathBuf>,
+    output: Option<PathBuf>,
+    changes: Mutex<Changes>,
+}
+
+#[derive(Default)]
+struct Changes {
+    /// Path, action and size of each uploaded or deleted file
+    files: Vec<(String, String, u64)>,
+    uploaded: usize,
+    deleted: usize,
+    failed: usize,
+    bytes: u64,
+}
+
+/// Whether thumper runs in a GitHub Actions workflow
+pub fn detected() -> bool {
+    env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true")
+}
+
+impl GithubActions {
+    pub fn new(title: String, step_summary: Option<PathBuf>, output: Option<PathBuf>) -> Self {
+        GithubActions {
+            title,
+            step_summary,
+            output,
+            changes: Mutex::new(Changes::default()),
+        }
+    }
+
+    /// Reports to the files GitHub Actions gives in `GITHUB_STEP_SUMMARY` and `GITHUB_OUTPUT`
+    pub fn from_env(title: String) -> Option<Self> {
+        let file = |name| env::var_os(name).map(PathBuf::from);
+        detected()
+            .then(|| GithubActions::new(title, file("GITHUB_STEP_SUMMARY"), file("GITHUB_OUTPUT")))
+    }
+
+    /// Write the step summary and the step outputs
+    pub fn finish(&self) -> io::Result<()> {
+        let Ok(changes) = self.changes.lock() else {
+            return Ok(());
+        };
+        if let Some(path) = &self.step_summary {
+            append(path, changes.markdown(self.title.as_str()).as_str())?;
+        }
+        if let Some(path) = &self.output {
+            append(path, changes.outputs().as_str())?;
+        }
+        Ok(())
+    }
+}
+
+fn append(path: &PathBuf, content: &str) -> io::Result<()> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(content.as_bytes())
+}
+
+impl Observer for GithubActions {
+    fn observe(&self, event: &Event) {
+        let Ok(mut changes) = self.changes.lock() else {
+            return;
+        };
+        match event {
+            // The title tells a dry run apart, its jobs changed nothing
+            Event::Job { dry_run: true, .. } => {}
+            Event::Job {
+                path,
+                action,
+                bytes,
+                ..
+            } => {
+                match *action {
+                    "put" => changes.uploaded += 1,
+                    "delete" | "trash" => changes.deleted += 1,
+                    _ => return,
+                }
+                changes.bytes += bytes;
+                changes
+                    .files
+                    .push((path.to_string(), action.to_string(), *bytes));
+            }
+            Event::JobFailed { path, error } => {
+                changes.failed += 1;
+                eprintln!("{}", command("error", Some(path), error));
+            }
+            Event::ListFailed { path, error } => {
+                let message = format!("Unable to list {path}, so no files are deleted: {error}");
+                eprintln!("{}", command("warning", None, message.as_str()));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Changes {
+    fn markdown(&self, title: &str) -> String {
+        let mut markdown = format!(
+            "### {title}\n\n\
+             | | Files | Size |\n\
+             |---|---:|---:|\n\
+             | Uploaded | {} | {} |\n\
+             | Deleted | {} | |\n\
+             | Failed | {} | |\n\n",
+            self.uploaded,
+            format_bytes(self.bytes),
+            self.deleted,
+            self.failed
+        );
+        if self.files.is_empty() {
+            markdown.push_str("No files changed.\n\n");
+            return markdown;
+        }
+        markdown.push_str(
+            "<details><summary>Changed files</summary>\n\n\
+             | File | Action | Size |\n\
+             |---|---|---:|\n",
+        );
+        for (path, action, bytes) in self.files.iter().take(MAX_ROWS) {
+            let size = if action == "put" {
+                format_bytes(*bytes)
+            } else {
+                String::new()
+            };
+            markdown.push_str(format!("| `{path}` | {action} | {size} |\n").as_str());
+        }
+        if self.files.len() > MAX_ROWS {
+            let more = self.files.len() - MAX_ROWS;
+            markdown.push_str(format!("\n...and {more} more\n").as_str());
+        }
+        markdown.push_str("\n</details>\n\n");
+        markdown
+    }
+
+    fn outputs(&self) -> String {
+        format!(
+            "changed_count={}\nuploaded_count={}\ndeleted_count={}\nfailed_count={}\nbytes_uploaded={}\n",
+            self.uploaded + self.deleted,
+            self.uploaded,
+            self.deleted,
+            self.failed,
+            self.bytes
+        )
+    }
+}
+
+/// A workflow command like `::error file=index.html::message`
+fn command(name: &str, file: Option<&str>, message: &str) -> String {
+    let escape = |data: &str| {
+        data.replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    };
+    match file {
+        Some(file) => {
+            let file = escape(file).replace(':', "%3A").replace(',', "%2C");
+            format!("::{name} file={file}::{}", escape(message))
+        }
+        None => format!("::{name}::{}", escape(message)),
+    }
+}
+
+/// Annotate the error thumper fails with, as a warning when a safety limit stopped the sync
+pub fn annotate(e: &anyhow::Error) {
+    let name = match error::find(e) {
+        Some(Error::SafetyLimit(_)) => "warning",
+        // Each failed file was annotated when it failed
+        Some(Error::SyncFailed { .. }) => return,
+        _ => "error",
+    };
+    eprintln!("{}", command(name, None, format!("{e:#}").as_str()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planning::Reason;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    #[test]
+    fn escapes_workflow_commands() {
+        assert_eq!(
+            command("error", Some("a,b:c.css"), "HTTP 503\n100% down"),
+            "::error file=a%2Cb%3Ac.css::HTTP 503%0A100%25 down"
+        );
+        assert_eq!(command("warning", None, "Careful"), "::warning::Careful");
+    }
+
+    #[test]
+    fn writes_step_summary_and_outputs() {
+        let dir = TempDir::new("github");
+        let github = GithubActions::new(
+            "Deployed to zone/site/".into(),
+            Some(dir.join("summary.md")),
+            Some(dir.join("output")),
+        );
+        for (path, action, bytes) in [
+            ("site/index.html", "put", 2048),
+            ("site/app.css", "unchanged", 0),
+            ("site/old.html", "delete", 0),
+        ] {
+            github.observe(&Event::Job {
+                path,
+                action,
+                reason: Reason::NewFile,
+                bytes,
+                size: bytes,
+                hash_ms: 0,
+                duration_ms: 0,
+                dry_run: false,
+            });
+        }
+        github.finish().unwrap();
+
+        let summary = fs::read_to_string(dir.join("summary.md")).unwrap();
+        assert!(summary.starts_with("### Deployed to zone/site/\n"));
+        assert!(summary.contains("| Uploaded | 1 | 2.0 KiB |\n"));
+        assert!(summary.contains("| `site/index.html` | put | 2.0 KiB |\n"));
+        assert!(summary.contains("| `site/old.html` | delete |  |\n"));
+        assert!(!summary.contains("app.css"));
+        assert_eq!(
+            fs::read_to_string(dir.join("output")).unwrap(),
+            "changed_count=2\nuploaded_count=1\ndeleted_count=1\nfailed_count=0\nbytes_uploaded=2048\n"
+        );
+    }
+}
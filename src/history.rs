@@ -0,0 +1,280 @@
+use crate::api::StorageZoneClient;
+use crate::cli::{HistoryArgs, KeepHistoryArgs, RestoreArgs};
+use crate::error::{self, Error};
+use crate::files::{Change, client_for, print_changes};
+use crate::trash::{self, older_than};
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+/// Where sync --history keeps previous versions of files, relative to the root of the storage zone
+pub const HISTORY_PREFIX: &str = ".thumper-history/";
+
+/// Which previous versions sync keeps of the files it overwrites
+pub struct HistoryPolicy {
+    pub deploy_id: String,
+    pub keep: usize,
+    pub max_age: Option<Duration>,
+}
+
+impl HistoryPolicy {
+    /// The policy for `args`, or `None` if sync should not keep any history
+    pub fn from_args(args: KeepHistoryArgs, deploy_id: &str) -> anyhow::Result<Option<Self>> {
+        let KeepHistoryArgs {
+            history,
+            history_keep,
+            history_max_age,
+        } = args;
+        if !history {
+            return Ok(None);
+        }
+        Ok(Some(HistoryPolicy {
+            deploy_id: deploy_id.to_string(),
+            keep: usize::try_from(history_keep)?,
+            max_age: history_max_age,
+        }))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Version {
+    version: String,
+    size: u64,
+    last_changed: String,
+    checksum: Option<String>,
+}
+
+fn versions_prefix(path: &str) -> String {
+    format!("{HISTORY_PREFIX}{}/", path.trim_start_matches('/'))
+}
+
+/// The previous versions of `path`, most recent first
+fn versions(client: &StorageZoneClient, path: &str) -> anyhow::Result<Vec<Version>> {
+    let mut versions: Vec<Version> = client
+        .ls_dir(versions_prefix(path).as_str())?
+        .into_iter()
+        .filter(|file| !file.is_directory)
+        .map(|file| Version {
+            version: file.object_name,
+            size: file.length,
+            last_changed: file.last_changed,
+            checksum: file.checksum,
+        })
+        .collect();
+    versions.sort_by(|a, b| b.version.cmp(&a.version));
+    Ok(versions)
+}
+
+/// Copy the current content of `remote` into its history before it is overwritten, then remove
+/// the versions that `policy` no longer keeps
+pub fn preserve(
+    client: &StorageZoneClient,
+    remote: &str,
+    policy: &HistoryPolicy,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let prefix = versions_prefix(remote);
+    client.copy_file(remote, &format!("{prefix}{}", policy.deploy_id))?;
+    for (index, Version { version, .. }) in versions(client, remote)?.into_iter().enumerate() {
+        let expired = policy
+            .max_age
+            .is_some_and(|age| older_than(version.as_str(), age, now));
+        if index >= policy.keep || expired {
+            client.delete_file(&format!("{prefix}{version}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Restore `version` of `path`, keeping the content it replaces as the version `deploy_id`
+fn restore(
+    client: &StorageZoneClient,
+    path: &str,
+    version: Option<&str>,
+    deploy_id: &str,
+    dry_run: bool,
+) -> anyhow::Result<Change> {
+    let path = path.trim_start_matches('/');
+    let versions = versions(client, path)?;
+    let version = match version {
+        Some(version) => versions.iter().find(|known| known.version == version),
+        None => versions.first(),
+    }
+    .ok_or_else(|| match version {
+        Some(version) => anyhow!("There is no version {version} of {path}"),
+        None => anyhow!("There are no previous versions of {path}"),
+    })?;
+    if !dry_run {
+        // Restoring is no reason to prune, so every version is kept
+        let policy = HistoryPolicy {
+            deploy_id: deploy_id.to_string(),
+            keep: usize::MAX,
+            max_age: None,
+        };
+        match preserve(client, path, &policy, Utc::now()) {
+            // There is nothing to keep when the file was deleted
+            Err(e) if matches!(error::find(&e), Some(Error::NotFound { .. })) => {}
+            preserved => preserved?,
+        }
+        client.copy_file(
+            &format!("{}{}", versions_prefix(path), version.version),
+            path,
+        )?;
+    }
+    Ok(Change {
+        path: path.to_string(),
+        action: "restore",
+        dry_run,
+    })
+}
+
+pub fn do_history(args: HistoryArgs) -> anyhow::Result<()> {
+    let HistoryArgs { zone, path, json } = args;
+    let client = client_for(zone)?;
+    let versions = versions(&client, path.as_str())?;
+    if json {
+        println!("{}", serde_json::to_string(&versions)?);
+    } else {
+        for Version {
+            version,
+            size,
+            checksum,
+            ..
+        } in versions
+        {
+            println!("{version}\t{size}\t{}", checksum.unwrap_or_default());
+        }
+    }
+    Ok(())
+}
+
+pub fn do_restore(args: RestoreArgs) -> anyhow::Result<()> {
+    let RestoreArgs {
+        zone,
+        path,
+        version,
+        dry_run,
+        json,
+    } = args;
+    let client = client_for(zone)?;
+    let deploy_id = trash::deploy_id();
+    let change = restore(
+        &client,
+        path.as_str(),
+        version.as_deref(),
+        deploy_id.as_str(),
+        dry_run,
+    )?;
+    print_changes(&[change], json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planning::SyncPlan;
+    use crate::testing::{FakeStorageZone, TempDir};
+    use crate::trash::DEPLOY_ID_FORMAT;
+    use crate::{ExecuteOptions, execute_sync};
+    use chrono::NaiveDateTime;
+    use std::fs;
+
+    fn time(deploy_id: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(deploy_id, DEPLOY_ID_FORMAT)
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn keeps_and_restores_overwritten_files() {
+        let zone = FakeStorageZone::start("history-zone");
+        zone.insert("site.css", b"body {}");
+        let client = zone.client();
+        let dir = TempDir::new("history");
+        let local = dir.join("site.css");
+        fs::write(&local, "body { color: red; }").unwrap();
+        let policy = HistoryPolicy {
+            deploy_id: "20250415T165233Z".into(),
+            keep: 10,
+            max_age: None,
+        };
+        let job = vec![SyncPlan::Replace {
+            local: local.into(),
+            remote: "site.css".into(),
+            remote_checksum: None,
+            remote_content_type: None,
+            remote_length: None,
+        }];
+        let options = ExecuteOptions {
+            history: Some(&policy),
+            ..Default::default()
+        };
+        execute_sync(options, job, &client, ".thumper.lock", 2).unwrap();
+        assert_eq!(
+            zone.get(".thumper-history/site.css/20250415T165233Z"),
+            Some(b"body {}".to_vec())
+        );
+        assert_eq!(zone.get("site.css"), Some(b"body { color: red; }".to_vec()));
+
+        let versions = versions(&client, "/site.css").unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].size, 7);
+
+        let restored_at = "20250416T000000Z";
+        assert!(
+            restore(
+                &client,
+                "site.css",
+                Some("20250101T000000Z"),
+                restored_at,
+                false
+            )
+            .is_err()
+        );
+        restore(&client, "site.css", None, restored_at, true).unwrap();
+        assert_eq!(zone.get("site.css"), Some(b"body { color: red; }".to_vec()));
+        restore(&client, "/site.css", None, restored_at, false).unwrap();
+        assert_eq!(zone.get("site.css"), Some(b"body {}".to_vec()));
+        assert_eq!(
+            zone.get(".thumper-history/site.css/20250416T000000Z"),
+            Some(b"body { color: red; }".to_vec())
+        );
+
+        zone.insert(".thumper-history/gone.css/20250415T165233Z", b"gone");
+        restore(&client, "gone.css", None, restored_at, false).unwrap();
+        assert_eq!(zone.get("gone.css"), Some(b"gone".to_vec()));
+    }
+
+    #[test]
+    fn prunes_by_count_and_age() {
+        let zone = FakeStorageZone::start("history-prune-zone");
+        zone.insert("index.html", b"current");
+        zone.insert(".thumper-history/index.html/20250101T000000Z", b"oldest");
+        zone.insert(".thumper-history/index.html/20250410T000000Z", b"older");
+        zone.insert(".thumper-history/index.html/20250414T000000Z", b"old");
+        let client = zone.client();
+
+        let by_count = HistoryPolicy {
+            deploy_id: "20250415T000000Z".into(),
+            keep: 3,
+            max_age: None,
+        };
+        preserve(&client, "index.html", &by_count, time("20250415T000000Z")).unwrap();
+        let kept: Vec<_> = versions(&client, "index.html")
+            .unwrap()
+            .into_iter()
+            .map(|version| version.version)
+            .collect();
+        assert_eq!(
+            kept,
+            vec!["20250415T000000Z", "20250414T000000Z", "20250410T000000Z"]
+        );
+
+        let by_age = HistoryPolicy {
+            deploy_id: "20250416T000000Z".into(),
+            keep: 10,
+            max_age: Some(Duration::days(3)),
+        };
+        preserve(&client, "index.html", &by_age, time("20250416T000000Z")).unwrap();
+        assert_eq!(versions(&client, "index.html").unwrap().len(), 3);
+    }
+}
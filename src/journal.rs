@@ -0,0 +1,232 @@
+use crate::local_path::LocalFile;
+use crate::planning::SyncPlan;
+use anyhow::Context;
+use fxhash::{FxHashMap, FxHashSet};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    fingerprint: String,
+    /// The content of the lockfile the sync wrote, to take over only its own lock on resume
+    #[serde(default)]
+    lock: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    remote: String,
+}
+
+/// A local record of the jobs a sync completed, so that an interrupted sync can be resumed.
+///
+/// The first line of the file holds the fingerprint of the sync and its lock, every other line a
+/// completed job.
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+    completed: FxHashSet<String>,
+}
+
+/// Identify a sync by its destination and the size and modification time of every local file,
+/// without reading them
+pub fn fingerprint(
+    local: &FxHashMap<String, LocalFile>,
+    storage_zone: &str,
+    path: &str,
+) -> anyhow::Result<String> {
+    let mut names: Vec<&String> = local.keys().collect();
+    names.sort();
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{storage_zone}\0{path}\n"));
+    for name in names {
+        let local = &local[name];
+        let metadata = fs::metadata(&local.path)
+            .with_context(|| format!("Unable to read {}", local.path.display()))?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+        hasher.update(format!("{name}\0{}\0{modified}\n", metadata.len()));
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Where the journal of syncs from `local_path` to `path` in `storage_zone` is kept by default
+pub fn default_path(storage_zone: &str, path: &str, local_path: &str) -> PathBuf {
+    let local_path = fs::canonicalize(local_path).unwrap_or_else(|_| PathBuf::from(local_path));
+    let key = Sha256::digest(format!("{storage_zone}\0{path}\0{}", local_path.display()));
+    env::temp_dir().join(format!("thumper-journal-{}.jsonl", hex::encode(&key[..8])))
+}
+
+/// The jobs an interrupted sync completed and the lock it held
+pub struct Interrupted {
+    lock: Option<String>,
+    completed: FxHashSet<String>,
+}
+
+impl Interrupted {
+    /// The content of the lockfile the interrupted sync wrote
+    pub fn lock(&self) -> Option<&str> {
+        self.lock.as_deref()
+    }
+}
+
+/// Read the journal at `path` that an interrupted sync with the same fingerprint left behind,
+/// without changing it
+pub fn interrupted(path: &Path, fingerprint: &str) -> anyhow::Result<Option<Interrupted>> {
+    let Ok(file) = File::open(path) else {
+        return Ok(None);
+    };
+    let mut lines = BufReader::new(file).lines();
+    let Some(header) = lines.next().transpose()? else {
+        return Ok(None);
+    };
+    let lock = match serde_json::from_str::<Header>(&header) {
+        Ok(header) if header.fingerprint == fingerprint => header.lock,
+        _ => {
+            warn!("Local files changed since the interrupted sync, starting over");
+            return Ok(None);
+        }
+    };
+    let mut completed = FxHashSet::default();
+    for line in lines {
+        // The last line may be cut short when the sync was killed while writing it
+        if let Ok(entry) = serde_json::from_str::<Entry>(&line?) {
+            completed.insert(entry.remote);
+        }
+    }
+    Ok(Some(Interrupted { lock, completed }))
+}
+
+impl Journal {
+    /// Start a journal at `path` for a sync holding `lock`, keeping the jobs completed by the
+    /// `earlier` run it resumes. Only open it once the lock is taken, it replaces any journal that
+    /// was there.
+    pub fn open(
+        path: PathBuf,
+        fingerprint: &str,
+        lock: &str,
+        earlier: Option<Interrupted>,
+    ) -> anyhow::Result<Self> {
+        let mut file = File::create(&path)?;
+        let header = Header {
+            fingerprint: fingerprint.to_string(),
+            lock: Some(lock.to_string()),
+        };
+        writeln!(file, "{}", serde_json::to_string(&header)?)?;
+        let completed = earlier.map(|earlier| earlier.completed).unwrap_or_default();
+        for remote in &completed {
+            let entry = Entry {
+                remote: remote.clone(),
+            };
+            writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        }
+        file.flush()?;
+        Ok(Journal {
+            path,
+            file: file.into(),
+            completed,
+        })
+    }
+
+    /// The jobs that were not completed by an earlier run
+    pub fn pending(&self, job: Vec<SyncPlan>) -> Vec<SyncPlan> {
+        job.into_iter()
+            .filter(|plan| !self.completed.contains(plan.remote()))
+            .collect()
+    }
+
+    /// Note that the job for `remote` is done
+    pub fn record(&self, remote: &str) -> anyhow::Result<()> {
+        let entry = Entry {
+            remote: remote.to_string(),
+        };
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(file.flush()?)
+    }
+
+    /// Remove the journal after a sync completed
+    pub fn finish(self) -> anyhow::Result<()> {
+        Ok(fs::remove_file(&self.path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeStorageZone, TempDir};
+    use crate::{ExecuteOptions, execute_sync};
+
+    fn plans(names: &[&str], dir: &Path) -> Vec<SyncPlan> {
+        names
+            .iter()
+            .map(|name| SyncPlan::Put {
+                local: dir.join(name).into(),
+                remote: name.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resumes_only_with_same_fingerprint() {
+        let dir = TempDir::new("journal");
+        let path = dir.join("journal.jsonl");
+
+        let journal = Journal::open(path.clone(), "one", "lock-1", None).unwrap();
+        journal.record("a.css").unwrap();
+        drop(journal);
+
+        let earlier = interrupted(&path, "one").unwrap().unwrap();
+        assert_eq!(earlier.lock(), Some("lock-1"));
+        let resumed = Journal::open(path.clone(), "one", "lock-2", Some(earlier)).unwrap();
+        let pending = resumed.pending(plans(&["a.css", "b.css"], &dir));
+        assert_eq!(pending, plans(&["b.css"], &dir));
+        resumed.record("b.css").unwrap();
+        drop(resumed);
+        let earlier = interrupted(&path, "one").unwrap().unwrap();
+        assert_eq!(earlier.lock(), Some("lock-2"));
+        assert!(
+            Journal::open(path.clone(), "one", "lock-3", Some(earlier))
+                .unwrap()
+                .pending(plans(&["a.css", "b.css"], &dir))
+                .is_empty()
+        );
+
+        assert!(interrupted(&path, "two").unwrap().is_none());
+        let changed = Journal::open(path.clone(), "two", "lock-4", None).unwrap();
+        assert_eq!(changed.pending(plans(&["a.css"], &dir)).len(), 1);
+        changed.finish().unwrap();
+        assert!(!path.exists());
+        assert!(interrupted(&path, "two").unwrap().is_none());
+    }
+
+    #[test]
+    fn records_completed_jobs_until_failure() {
+        let dir = TempDir::new("journal-sync");
+        fs::write(dir.join("a.css"), "a").unwrap();
+        let zone = FakeStorageZone::start("journal-zone");
+        let journal =
+            Journal::open(dir.join("journal.jsonl"), "fingerprint", "lock", None).unwrap();
+        let options = ExecuteOptions {
+            journal: Some(&journal),
+            ..Default::default()
+        };
+        let job = plans(&["a.css", "missing.css"], &dir);
+
+        let err =
+            execute_sync(options, job.clone(), &zone.client(), ".thumper.lock", 1).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("missing.css"), "{message}");
+        assert!(message.contains("1 of 2 files done"), "{message}");
+        drop(journal);
+        let earlier = interrupted(&dir.join("journal.jsonl"), "fingerprint").unwrap();
+        let resumed = Journal::open(dir.join("journal.jsonl"), "fingerprint", "lock", earlier);
+        assert_eq!(resumed.unwrap().pending(job), plans(&["missing.css"], &dir));
+    }
+}
@@ -1,14 +1,71 @@
 use anyhow::{Context, anyhow};
 use fxhash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct LocalFile {
+    pub path: PathBuf,
+    /// Precomputed SHA-256 of the content, trusted instead of hashing the file
+    #[serde(with = "hex_checksum")]
+    pub checksum: Option<[u8; 32]>,
+    /// Precomputed content type, used instead of sniffing the file
+    pub content_type: Option<String>,
+}
+
+impl From<PathBuf> for LocalFile {
+    fn from(path: PathBuf) -> Self {
+        LocalFile {
+            path,
+            checksum: None,
+            content_type: None,
+        }
+    }
+}
+
+/// Serialize checksums as hex strings, the way bunny.net presents them
+pub mod hex_checksum {
+    use serde::{Deserialize, Deserializer, Serializer, de};
+
+    pub fn serialize<S: Serializer>(
+        checksum: &Option<[u8; 32]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match checksum {
+            Some(checksum) => serializer.serialize_some(&hex::encode_upper(checksum)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<[u8; 32]>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|hex_checksum| {
+                let mut checksum = [0; 32];
+                hex::decode_to_slice(hex_checksum.as_bytes(), &mut checksum)
+                    .map_err(de::Error::custom)?;
+                Ok(checksum)
+            })
+            .transpose()
+    }
+}
+
+fn remote_name_for(remote_root: &str, name: String) -> String {
+    let remote_root = remote_root.trim_start_matches("/").trim_end_matches("/");
+    if remote_root.is_empty() {
+        name
+    } else {
+        format!("{remote_root}/{name}")
+    }
+}
 
 pub fn files_by_remote_name(
     root: &str,
     remote_root: &str,
-) -> anyhow::Result<FxHashMap<String, PathBuf>> {
+) -> anyhow::Result<FxHashMap<String, LocalFile>> {
     let files = discover_files(root)?;
-    let remote_root = remote_root.trim_start_matches("/").trim_end_matches("/");
     let mut by_name = FxHashMap::default();
     for file in files {
         let remote_name = file
@@ -16,15 +73,122 @@ pub fn files_by_remote_name(
             .to_str()
             .context("Invalid utf8")?
             .to_owned();
-        if remote_root.is_empty() {
-            by_name.insert(remote_name, file);
-        } else {
-            by_name.insert(format!("{remote_root}/{remote_name}"), file);
-        }
+        by_name.insert(remote_name_for(remote_root, remote_name), file.into());
     }
     Ok(by_name)
 }
 
+#[derive(Debug, Deserialize)]
+struct ListedFile {
+    path: String,
+    sha256: Option<String>,
+    content_type: Option<String>,
+}
+
+fn parse_file_list(list: &str, content: &str) -> anyhow::Result<Vec<ListedFile>> {
+    if list.ends_with(".json") {
+        serde_json::from_str(content).with_context(|| format!("Unable to parse {list}"))
+    } else {
+        Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| ListedFile {
+                path: line.to_string(),
+                sha256: None,
+                content_type: None,
+            })
+            .collect())
+    }
+}
+
+/// Build the local side of a sync from a list of files relative to `root` instead of walking it.
+///
+/// `list` is either plain text with one path per line, or a JSON array of objects with `path`
+/// and optional `sha256` and `content_type`. Every listed file must exist.
+pub fn files_from_list(
+    list: &str,
+    root: &str,
+    remote_root: &str,
+) -> anyhow::Result<FxHashMap<String, LocalFile>> {
+    let content = fs::read_to_string(list).with_context(|| format!("Unable to read {list}"))?;
+    let root_path = Path::new(root);
+    if !root_path.is_dir() {
+        return Err(anyhow!("{root} is not a directory"));
+    }
+    let mut by_name = FxHashMap::default();
+    let mut missing = vec![];
+
+    for ListedFile {
+        path,
+        sha256,
+        content_type,
+    } in parse_file_list(list, &content)?
+    {
+        let relative = Path::new(path.as_str());
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(anyhow!(
+                "{path} in {list} is not a relative path inside {root}"
+            ));
+        }
+        let checksum = sha256
+            .map(|hex_checksum| {
+                let mut checksum = [0; 32];
+                hex::decode_to_slice(hex_checksum.as_bytes(), &mut checksum)
+                    .with_context(|| format!("Invalid sha256 for {path} in {list}"))?;
+                Ok::<[u8; 32], anyhow::Error>(checksum)
+            })
+            .transpose()?;
+        let physical_path = root_path.join(relative);
+        if !physical_path.is_file() {
+            missing.push(path);
+            continue;
+        }
+        let remote_name = relative
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        by_name.insert(
+            remote_name_for(remote_root, remote_name),
+            LocalFile {
+                path: physical_path,
+                checksum,
+                content_type,
+            },
+        );
+    }
+
+    if missing.is_empty() {
+        Ok(by_name)
+    } else {
+        missing.sort();
+        Err(anyhow!(
+            "{} files listed in {list} are missing from {root}: {}",
+            missing.len(),
+            missing.join(", ")
+        ))
+    }
+}
+
+/// The files to sync from `root`, either from the list in `from_list` or by walking the tree
+pub fn local_files(
+    from_list: Option<&str>,
+    root: &str,
+    remote_root: &str,
+) -> anyhow::Result<FxHashMap<String, LocalFile>> {
+    match from_list {
+        Some(list) => files_from_list(list, root, remote_root),
+        None => files_by_remote_name(root, remote_root),
+    }
+}
+
 fn discover_files(root: &str) -> anyhow::Result<FxHashSet<PathBuf>> {
     let root_path = PathBuf::from(root);
     let mut files = FxHashSet::default();
@@ -48,13 +212,63 @@ fn discover_files(root: &str) -> anyhow::Result<FxHashSet<PathBuf>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::TempDir;
 
     #[test]
     fn files_by_remote_name_smoketest() {
         let files = files_by_remote_name("src", "sources").unwrap();
         assert_eq!(
-            files.get("sources/main.rs"),
+            files.get("sources/main.rs").map(|file| &file.path),
             Some(&PathBuf::new().join("src").join("main.rs"))
         );
     }
+
+    #[test]
+    fn files_from_plain_list() {
+        let dir = TempDir::new("plain-list");
+        let list = dir.join("list.txt");
+        fs::write(&list, "# comment\nmain.rs\n\n./cli.rs\n").unwrap();
+        let files = files_from_list(list.to_str().unwrap(), "src", "/sources/").unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files.get("sources/main.rs"),
+            Some(&LocalFile::from(PathBuf::new().join("src").join("main.rs")))
+        );
+        assert!(files.contains_key("sources/cli.rs"));
+    }
+
+    #[test]
+    fn files_from_json_list_keeps_precomputed_metadata() {
+        let dir = TempDir::new("json-list");
+        let list = dir.join("list.json");
+        let checksum = "FD9495967478FCD8B9FB08F70EAF2806BD50F4AB2261BE16A9BEAA542C37A441";
+        fs::write(
+            &list,
+            format!(
+                r#"[{{"path": "main.rs", "sha256": "{checksum}", "content_type": "text/x-rust"}}]"#
+            ),
+        )
+        .unwrap();
+        let files = files_from_list(list.to_str().unwrap(), "src", "/").unwrap();
+        let main = files.get("main.rs").unwrap();
+        assert_eq!(main.checksum.map(hex::encode_upper), Some(checksum.into()));
+        assert_eq!(main.content_type.as_deref(), Some("text/x-rust"));
+    }
+
+    #[test]
+    fn files_from_list_rejects_missing_files() {
+        let dir = TempDir::new("missing-list");
+        let list = dir.join("list.txt");
+        fs::write(&list, "main.rs\nnope.rs\n").unwrap();
+        let err = files_from_list(list.to_str().unwrap(), "src", "/").unwrap_err();
+        assert!(err.to_string().contains("nope.rs"));
+    }
+
+    #[test]
+    fn files_from_list_rejects_escaping_paths() {
+        let dir = TempDir::new("escape-list");
+        let list = dir.join("list.txt");
+        fs::write(&list, "../Cargo.toml\n").unwrap();
+        assert!(files_from_list(list.to_str().unwrap(), "src", "/").is_err());
+    }
 }
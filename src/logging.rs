@@ -0,0 +1,173 @@
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::RwLock;
+
+/// Shown in place of access keys and API keys in every log line
+const REDACTED: &str = "[redacted]";
+
+/// Keys that must never show up in log output, added with `redact`
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum LogFormat {
+    /// Lines like `WARNING: message key=value`
+    #[default]
+    Text,
+    /// One JSON object per line with time, level, target, message and fields
+    Json,
+}
+
+/// The level to log at for `-q` and the number of `-v` flags
+pub fn level(quiet: bool, verbose: u8) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+/// Log to stderr in `format` from `level` and up
+pub fn init(level: LevelFilter, format: LogFormat) {
+    if log::set_logger(Box::leak(Box::new(Logger { format }))).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Never log `secret`, like the access key of a request
+pub fn redact(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let known = SECRETS
+        .read()
+        .is_ok_and(|secrets| secrets.iter().any(|known| known == secret));
+    if !known && let Ok(mut secrets) = SECRETS.write() {
+        secrets.push(secret.to_string());
+    }
+}
+
+fn redacted(text: String) -> String {
+    let Ok(secrets) = SECRETS.read() else {
+        return text;
+    };
+    secrets
+        .iter()
+        .fold(text, |text, secret| text.replace(secret.as_str(), REDACTED))
+}
+
+struct Logger {
+    format: LogFormat,
+}
+
+impl Log for Logger {
+    /// Dependencies only get to log warnings and errors, their debug output drowns out ours
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+            && (metadata.level() <= Level::Warn || metadata.target().starts_with("thumper"))
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", self.format(record));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl Logger {
+    fn format(&self, record: &Record) -> String {
+        let mut fields = Fields::default();
+        let _ = record.key_values().visit(&mut fields);
+        let line = match self.format {
+            LogFormat::Text => {
+                let level = match record.level() {
+                    Level::Warn => "WARNING",
+                    level => level.as_str(),
+                };
+                let mut line = format!("{level}: {}", record.args());
+                for (key, value) in &fields.0 {
+                    let value = match value {
+                        serde_json::Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    };
+                    line.push_str(format!(" {key}={value}").as_str());
+                }
+                line
+            }
+            LogFormat::Json => {
+                let mut line = serde_json::Map::new();
+                let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+                line.insert("time".into(), time.into());
+                line.insert("level".into(), record.level().as_str().into());
+                line.insert("target".into(), record.target().into());
+                line.insert("message".into(), record.args().to_string().into());
+                line.extend(fields.0);
+                serde_json::Value::Object(line).to_string()
+            }
+        };
+        redacted(line)
+    }
+}
+
+/// The key-value pairs of a record, numbers kept as numbers for JSON
+#[derive(Default)]
+struct Fields(Vec<(String, serde_json::Value)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = match value.to_u64() {
+            Some(number) => number.into(),
+            None => value.to_string().into(),
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_fields_and_redacts_secrets() {
+        redact("hunter2-storage-key");
+        let text = Logger {
+            format: LogFormat::Text,
+        };
+        let json = Logger {
+            format: LogFormat::Json,
+        };
+        let args = format_args!("Sent with hunter2-storage-key");
+        let kvs = [
+            ("status", Value::from(200u64)),
+            ("method", Value::from("GET")),
+        ];
+        let record = Record::builder()
+            .args(args)
+            .level(Level::Trace)
+            .target("thumper::api")
+            .key_values(&kvs)
+            .build();
+
+        assert_eq!(
+            text.format(&record),
+            "TRACE: Sent with [redacted] status=200 method=GET"
+        );
+        let mut line: serde_json::Value = serde_json::from_str(&json.format(&record)).unwrap();
+        line.as_object_mut().unwrap().remove("time");
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "level": "TRACE",
+                "target": "thumper::api",
+                "message": "Sent with [redacted]",
+                "status": 200,
+                "method": "GET",
+            })
+        );
+    }
+}
@@ -1,64 +1,203 @@
-use crate::api::StorageZoneClient;
-use crate::cli::{Action, Cli, SyncArgs};
-use crate::planning::{Execution, SyncAction, SyncPlan, plan_execution, plan_sync};
+use crate::api::{FileInfo, StorageZoneClient};
+use crate::cli::{Action, Cli, DeleteLimitArgs, SyncArgs};
+use crate::error::{Error, ErrorFormat, Failure};
+use crate::events::{Event, Events, Output};
+use crate::github::GithubActions;
+use crate::history::HistoryPolicy;
+use crate::journal::Journal;
+use crate::metrics::Metrics;
+use crate::notify::{Deploy, Notifier};
+use crate::planning::{
+    Execution, Reason, SyncAction, SyncPlan, check_delete_limits, plan_empty_directories,
+    plan_execution, plan_sync,
+};
+use crate::progress::ProgressDisplay;
+use crate::summary::Summarizer;
 use anyhow::{Context, anyhow};
-use chrono::Local;
+use chrono::{Local, Utc};
 use clap::{CommandFactory, Parser};
 use clap_complete::Shell::{Bash, Elvish, Fish, PowerShell, Zsh};
 use clap_complete::generate;
 use crossbeam::channel::unbounded;
 use fxhash::FxHashMap;
-use std::{env, fs, io, thread};
+use log::warn;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, fs, io, process, thread};
 
 mod api;
+mod check;
 mod cli;
+mod diff;
+mod error;
+mod events;
+mod files;
+mod github;
+mod history;
+mod journal;
 mod local_path;
+mod logging;
+mod metrics;
+mod notify;
+mod plan;
 mod planning;
+mod previews;
+mod progress;
+mod pull;
+mod release;
+mod review;
+mod summary;
+#[cfg(test)]
+mod testing;
+mod trash;
+
+/// How `execute_sync` carries out a job
+#[derive(Clone, Copy, Default)]
+struct ExecuteOptions<'a> {
+    dry_run: bool,
+    /// Show diffs of replaced files up to this many bytes in dry runs
+    diff: Option<usize>,
+    /// Move deleted files below this prefix instead of deleting them
+    trash: Option<&'a str>,
+    /// The listing of the files to move to the trash, so that they are not listed one by one. Set
+    /// by `execute_sync`
+    trashed: Option<&'a FxHashMap<String, FileInfo>>,
+    /// Keep the old content of replaced files
+    history: Option<&'a HistoryPolicy>,
+    /// Record completed jobs, to resume the sync if it is interrupted
+    journal: Option<&'a Journal>,
+    /// Run every job even when some fail, and only delete once all uploads succeeded
+    keep_going: bool,
+}
+
+struct JobOutcome {
+    remote: String,
+    event: &'static str,
+    reason: Reason,
+    /// Size of the uploaded file
+    bytes: u64,
+    /// Size of the local file, whether it was uploaded or not
+    size: u64,
+    /// Time spent reading and hashing the local file
+    hash_ms: u64,
+    diff: Option<String>,
+}
 
 fn execute_job(
     client: &StorageZoneClient,
     job: SyncPlan,
-    dry_run: bool,
+    options: ExecuteOptions,
     lockfile: &str,
-) -> anyhow::Result<(String, &'static str)> {
-    let Execution { remote, action } = plan_execution(&job, fs::read)?;
+) -> anyhow::Result<JobOutcome> {
+    let ExecuteOptions {
+        dry_run,
+        diff,
+        trash,
+        history,
+        ..
+    } = options;
+    let hashing = Instant::now();
+    let Execution {
+        remote,
+        action,
+        reason,
+    } = plan_execution(&job, fs::read)?;
+    let hash_ms = millis(hashing.elapsed());
 
     let event = match &action {
         SyncAction::Put { .. } => "put",
+        SyncAction::Ignore if reason == Reason::ProtectedByIgnore => "keep",
         SyncAction::Ignore => "unchanged",
+        SyncAction::Delete if trash.is_some() && remote != lockfile => "trash",
         SyncAction::Delete => "delete",
     };
+    let bytes = match &action {
+        SyncAction::Put { content, .. } if !dry_run => content.len() as u64,
+        _ => 0,
+    };
+    let size = local_size(&job);
+    let mut change = None;
     if !dry_run {
         match action {
             SyncAction::Put { content, mime_type } => {
-                client.put_file(remote, content, mime_type)?;
-            }
-            SyncAction::Delete if remote != lockfile => {
-                client.delete_file(remote)?;
+                if let (Some(policy), SyncPlan::Replace { .. }) = (history, &job) {
+                    history::preserve(client, remote, policy, Utc::now())?;
+                }
+                client.put_file(remote, content, mime_type.as_deref())?;
             }
+            SyncAction::Delete if remote != lockfile => match trash {
+                Some(prefix) => {
+                    let to = format!("{prefix}{remote}");
+                    match options.trashed.and_then(|trashed| trashed.get(remote)) {
+                        Some(source) => client.move_listed(source, remote, to.as_str())?,
+                        None => client.move_file(remote, to.as_str())?,
+                    }
+                }
+                None => client.delete_file(remote)?,
+            },
             _ => {}
         }
+    } else if let (
+        Some(max_bytes),
+        SyncPlan::Replace {
+            remote_checksum,
+            remote_length,
+            ..
+        },
+        SyncAction::Put { content, mime_type },
+    ) = (diff, &job, &action)
+    {
+        let mime_type = mime_type.as_deref();
+        // Only download what can be diffed, the listing has enough to summarize anything else
+        change = Some(
+            match diff::is_diffable(remote, mime_type, *remote_length, content.len(), max_bytes) {
+                true => {
+                    let old = client.download_file(remote)?;
+                    diff::describe_change(remote, mime_type, &old, content, max_bytes)
+                }
+                false => diff::summarize_change(remote_checksum.as_ref(), content),
+            },
+        );
     }
 
-    Ok((remote.to_string(), event))
+    Ok(JobOutcome {
+        remote: remote.to_string(),
+        event,
+        reason,
+        bytes,
+        size,
+        hash_ms,
+        diff: change,
+    })
 }
 
-fn execute_sync(
-    verbose: bool,
-    dry_run: bool,
+/// The jobs of one phase of a sync that completed and failed
+#[derive(Default)]
+struct Progress {
+    completed: usize,
+    failed: Vec<(String, anyhow::Error)>,
+    /// Bytes uploaded by the completed jobs
+    bytes: u64,
+}
+
+fn run_jobs(
+    options: ExecuteOptions,
     job: Vec<SyncPlan>,
     client: &StorageZoneClient,
     lockfile: &str,
     concurrency: usize,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Progress> {
     let (send_work, receive_work) = unbounded();
     let (send_result, receive_result) = unbounded();
-    let expected = job.len();
 
     thread::scope(move |scope| {
         for action in job {
             send_work.send(action)?;
         }
+        drop(send_work);
 
         for _ in 0..concurrency {
             let receive_work = receive_work.clone();
@@ -66,40 +205,328 @@ fn execute_sync(
 
             scope.spawn(move || {
                 while let Ok(action) = receive_work.recv() {
-                    let r = execute_job(client, action, dry_run, lockfile);
-                    send_result.send(r)?;
+                    let remote = action.remote().to_string();
+                    let started = Instant::now();
+                    let r = execute_job(client, action, options, lockfile);
+                    send_result.send((remote, r, started.elapsed()))?;
                 }
                 Ok::<(), anyhow::Error>(())
             });
         }
+        drop(send_result);
 
-        for _ in 0..expected {
-            let (remote, event) = receive_result.recv()??;
-            if verbose || dry_run {
-                println!("{remote}: {event}");
+        let events = client.events();
+        let mut progress = Progress::default();
+        // Ends once every worker is done, so that the outcome of jobs in flight is never lost
+        for (remote, outcome, duration) in receive_result {
+            let JobOutcome {
+                remote,
+                event,
+                reason,
+                bytes,
+                size,
+                hash_ms,
+                diff,
+            } = match outcome {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    events.emit(Event::JobFailed {
+                        path: remote.as_str(),
+                        error: format!("{e:#}"),
+                    });
+                    progress.failed.push((remote, e));
+                    if !options.keep_going {
+                        // Leave the jobs nobody started yet
+                        while receive_work.try_recv().is_ok() {}
+                    }
+                    continue;
+                }
+            };
+            progress.completed += 1;
+            progress.bytes += bytes;
+            if let Some(journal) = options.journal {
+                journal.record(remote.as_str())?;
+            }
+            events.emit(Event::Job {
+                path: remote.as_str(),
+                action: event,
+                reason,
+                bytes,
+                size,
+                hash_ms,
+                duration_ms: millis(duration),
+                dry_run: options.dry_run,
+            });
+            if let Some(diff) = diff {
+                print!("{diff}");
             }
         }
+        Ok(progress)
+    })
+}
 
-        drop(send_work);
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
 
-        Ok::<_, anyhow::Error>(())
-    })
+fn execute_sync(
+    options: ExecuteOptions,
+    job: Vec<SyncPlan>,
+    client: &StorageZoneClient,
+    lockfile: &str,
+    concurrency: usize,
+) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let expected = job.len();
+    let trashed = match options.trash {
+        Some(_) if !options.dry_run => {
+            let deletes: Vec<&str> = job
+                .iter()
+                .filter(|plan| matches!(plan, SyncPlan::Delete { .. }) && plan.remote() != lockfile)
+                .map(SyncPlan::remote)
+                .collect();
+            client.stat_all(&deletes)?
+        }
+        _ => FxHashMap::default(),
+    };
+    let options = ExecuteOptions {
+        trashed: Some(&trashed),
+        ..options
+    };
+    let (progress, skipped_deletes) = if options.keep_going {
+        // Uploads go first, and deletes only run once every upload succeeded, so that a failed
+        // upload never leaves the site without a file that is still linked to
+        let (deletes, uploads): (Vec<SyncPlan>, Vec<SyncPlan>) = job
+            .into_iter()
+            .partition(|plan| matches!(plan, SyncPlan::Delete { .. }));
+        let mut progress = run_jobs(options, uploads, client, lockfile, concurrency)?;
+        if progress.failed.is_empty() {
+            let deleted = run_jobs(options, deletes, client, lockfile, concurrency)?;
+            progress.completed += deleted.completed;
+            progress.bytes += deleted.bytes;
+            progress.failed.extend(deleted.failed);
+            (progress, 0)
+        } else {
+            (progress, deletes.len())
+        }
+    } else {
+        (run_jobs(options, job, client, lockfile, concurrency)?, 0)
+    };
+    client.events().emit(Event::Summary {
+        completed: progress.completed,
+        failed: progress.failed.len(),
+        bytes: progress.bytes,
+        duration_ms: millis(started.elapsed()),
+    });
+    if progress.failed.is_empty() {
+        Ok(())
+    } else {
+        Err(sync_failure(progress, expected, skipped_deletes, options))
+    }
 }
 
-fn take_lock(client: &StorageZoneClient, lockfile: &str, force: bool) -> anyhow::Result<()> {
-    if let Ok(sync_time) = client.read_file(lockfile) {
-        eprintln!("WARNING: Remote is locked since {sync_time}");
-        if !force {
-            return Err(anyhow!("Dangling lock in {lockfile} prevents sync"));
+/// What kind of failure `e` is, like `HTTP 503 Service Unavailable` or `I/O: entity not found`
+fn failure_kind(e: &anyhow::Error) -> String {
+    if let Some(e) = error::find(e) {
+        return match e {
+            Error::Unauthorized { status, .. } | Error::Http { status, .. } => {
+                format!("HTTP {status}")
+            }
+            Error::NotFound { .. } => "HTTP 404 Not Found".to_string(),
+            Error::Network { source, .. } if source.is_timeout() => "Timeout".to_string(),
+            Error::Network { .. } => "Connection failed".to_string(),
+            e => e.code().replace('_', " "),
+        };
+    }
+    match e
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<io::Error>())
+    {
+        Some(e) => format!("I/O: {}", e.kind()),
+        None => "Other".to_string(),
+    }
+}
+
+/// Summarize a sync that stopped because of failed jobs, grouped by the kind of failure
+fn sync_failure(
+    progress: Progress,
+    expected: usize,
+    skipped_deletes: usize,
+    options: ExecuteOptions,
+) -> anyhow::Error {
+    let Progress {
+        completed, failed, ..
+    } = progress;
+    let mut summary = format!(
+        "Sync failed, {completed} of {expected} files done, {} failed:",
+        failed.len()
+    );
+    let mut kinds: BTreeMap<String, Vec<Failure>> = BTreeMap::new();
+    for (path, e) in failed {
+        let kind = failure_kind(&e);
+        kinds.entry(kind.clone()).or_default().push(Failure {
+            path,
+            kind,
+            error: format!("{e:#}"),
+        });
+    }
+    let mut failures = vec![];
+    for (kind, mut failed) in kinds {
+        failed.sort_by(|a, b| a.path.cmp(&b.path));
+        summary.push_str(&format!("\n  {kind} ({}):", failed.len()));
+        for Failure { path, error, .. } in &failed {
+            summary.push_str(&format!("\n    {path}: {error}"));
         }
+        failures.extend(failed);
+    }
+    if skipped_deletes > 0 {
+        summary.push_str(&format!(
+            "\nSkipped {skipped_deletes} deletes because uploads failed"
+        ));
+    }
+    if options.journal.is_some() {
+        summary.push_str("\nRun the sync again with --resume to continue where it stopped");
+    }
+    Error::SyncFailed {
+        message: summary,
+        failures,
+    }
+    .into()
+}
+
+/// The event with the number of each kind of job in `job`
+fn plan_ready<'a>(job: &[SyncPlan], empty_directories: &[String]) -> Event<'a> {
+    let count = |matches: fn(&SyncPlan) -> bool| job.iter().filter(|plan| matches(plan)).count();
+    Event::PlanReady {
+        new_files: count(|plan| matches!(plan, SyncPlan::Put { .. })),
+        existing_files: count(|plan| matches!(plan, SyncPlan::Replace { .. })),
+        deletes: count(|plan| matches!(plan, SyncPlan::Delete { .. })),
+        kept: count(|plan| matches!(plan, SyncPlan::Keep { .. })),
+        empty_directories: empty_directories.len(),
+        bytes: job.iter().map(local_size).sum(),
+    }
+}
+
+/// Size of the local file of `plan`, if it has one
+fn local_size(plan: &SyncPlan) -> u64 {
+    plan.local()
+        .and_then(|local| fs::metadata(&local.path).ok())
+        .map_or(0, |metadata| metadata.len())
+}
+
+fn delete_directories(
+    dry_run: bool,
+    directories: &[String],
+    client: &StorageZoneClient,
+) -> anyhow::Result<()> {
+    for directory in directories {
+        let started = Instant::now();
+        if !dry_run {
+            client.delete_file(directory)?;
+        }
+        client.events().emit(Event::Job {
+            path: directory.as_str(),
+            action: "delete",
+            reason: Reason::EmptyDirectory,
+            bytes: 0,
+            size: 0,
+            hash_ms: 0,
+            duration_ms: millis(started.elapsed()),
+            dry_run,
+        });
+    }
+    Ok(())
+}
+
+fn enforce_delete_limits(
+    limits: &DeleteLimitArgs,
+    job: &[SyncPlan],
+    remote_files: usize,
+    lockfile: &str,
+) -> anyhow::Result<()> {
+    if limits.allow_mass_delete {
+        return Ok(());
+    }
+    check_delete_limits(
+        job,
+        remote_files,
+        lockfile,
+        limits.max_delete,
+        limits.max_delete_percent,
+    )?;
+    Ok(())
+}
+
+fn review_if_interactive(
+    interactive: bool,
+    job: Vec<SyncPlan>,
+    empty_directories: Vec<String>,
+    lockfile: &str,
+) -> anyhow::Result<(Vec<SyncPlan>, Vec<String>)> {
+    if !interactive {
+        return Ok((job, empty_directories));
+    }
+    if !io::stdin().is_terminal() {
+        warn!("stdin is not a terminal, running without interactive review");
+        return Ok((job, empty_directories));
+    }
+    review::review_plan(
+        job,
+        empty_directories,
+        lockfile,
+        io::stdin().lock(),
+        io::stdout(),
+    )
+}
+
+/// Lock the remote and return the content of the lockfile. A lock held by someone else is only
+/// taken over with `force`, or when it is the `owned` lock an interrupted sync left behind.
+fn take_lock(
+    client: &StorageZoneClient,
+    lockfile: &str,
+    force: bool,
+    owned: Option<&str>,
+) -> anyhow::Result<String> {
+    match client.read_lock(lockfile) {
+        Ok(sync_time) if owned == Some(sync_time.as_str()) => {}
+        Ok(sync_time) if force => warn!("Remote is locked since {sync_time}"),
+        Ok(sync_time) => {
+            return Err(Error::LockHeld {
+                lockfile: lockfile.to_string(),
+                since: sync_time,
+            }
+            .into());
+        }
+        Err(Error::NotFound { .. }) => {}
+        Err(e) => return Err(e.into()),
     }
     let now = Local::now();
     let ts = now.to_rfc3339();
-    client.put_file(lockfile, ts.bytes().collect(), Some("text/plain"))
+    client.put_file(lockfile, ts.bytes().collect(), Some("text/plain"))?;
+    client.events().emit(Event::LockAcquired { lockfile });
+    Ok(ts)
 }
 
 fn remove_lock(client: &StorageZoneClient, lockfile: &str) -> anyhow::Result<()> {
-    client.delete_file(lockfile)
+    client.delete_file(lockfile)?;
+    client.events().emit(Event::LockReleased { lockfile });
+    Ok(())
+}
+
+/// Remove the lock once a command finished with `result`. Failing to remove it only fails the
+/// command if nothing else did, so that it never hides why the command failed.
+fn unlock<T>(
+    client: &StorageZoneClient,
+    lockfile: &str,
+    result: anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    match (remove_lock(client, lockfile), result) {
+        (Err(e), Err(failed)) => {
+            warn!("Unable to remove {lockfile}: {e:#}");
+            Err(failed)
+        }
+        (unlocked, result) => unlocked.and(result),
+    }
 }
 
 struct SyncJob {
@@ -117,6 +544,27 @@ fn normalize_path(mut path: String) -> String {
     }
 }
 
+fn init_client(
+    access_key: Option<String>,
+    storage_zone: String,
+    endpoint: String,
+) -> anyhow::Result<StorageZoneClient> {
+    let access_key = access_key
+        .or_else(|| env::var("THUMPER_KEY").ok())
+        .context("No API key provided with --access-key or THUMPER_KEY")?;
+    Ok(StorageZoneClient::new(access_key, endpoint, storage_zone))
+}
+
+/// Add the prefixes thumper keeps its own data in to `ignore`, so that they are never synced
+fn with_reserved_prefixes(mut ignore: Vec<String>) -> Vec<String> {
+    for reserved in [trash::TRASH_PREFIX, history::HISTORY_PREFIX] {
+        if !ignore.iter().any(|prefix| prefix == reserved) {
+            ignore.push(reserved.to_string());
+        }
+    }
+    ignore
+}
+
 fn init_sync(
     access_key: Option<String>,
     local_path: String,
@@ -124,10 +572,7 @@ fn init_sync(
     storage_zone: String,
     endpoint: String,
 ) -> anyhow::Result<SyncJob> {
-    let access_key = access_key
-        .or_else(|| env::var("THUMPER_KEY").ok())
-        .context("No API key provided with --access-key or THUMPER_KEY")?;
-    let client = StorageZoneClient::new(access_key, endpoint, storage_zone);
+    let client = init_client(access_key, storage_zone, endpoint)?;
 
     Ok(SyncJob {
         client,
@@ -149,7 +594,46 @@ fn do_sync(args: SyncArgs) -> anyhow::Result<()> {
         ignore,
         verbose,
         concurrency,
+        from_list,
+        diff,
+        diff_max_bytes,
+        interactive,
+        delete_limits,
+        trash,
+        history,
+        preview,
+        preview_template,
+        git_ref,
+        resume,
+        journal,
+        keep_going,
+        output,
+        no_progress,
+        summary,
+        metrics_file,
+        notify_url,
+        notify_secret,
+        notify_timeout,
     } = args;
+    let mut ignore = with_reserved_prefixes(ignore);
+    let path = match &preview {
+        Some(name) => previews::preview_path(preview_template.as_str(), name)?,
+        None => path,
+    };
+    if preview.is_some() {
+        ignore.push(format!(
+            "{}{}",
+            normalize_path(path.clone()).trim_start_matches('/'),
+            previews::PREVIEW_MARKER
+        ));
+    }
+    previews::reserve_previews(
+        &mut ignore,
+        preview_template.as_str(),
+        normalize_path(path.clone()).as_str(),
+    );
+    let deploy_id = trash::deploy_id();
+    let history = HistoryPolicy::from_args(history, deploy_id.as_str())?;
 
     let concurrency = concurrency.unwrap_or_else(num_cpus::get);
 
@@ -157,25 +641,158 @@ fn do_sync(args: SyncArgs) -> anyhow::Result<()> {
         client,
         path,
         local_path,
-    } = init_sync(access_key, local_path, path, storage_zone, endpoint)?;
-    if !dry_run {
-        take_lock(&client, lockfile.as_str(), force)?;
+    } = init_sync(access_key, local_path, path, storage_zone.clone(), endpoint)?;
+    let verbose = verbose || dry_run;
+    let mut observers = vec![output.observer(verbose)];
+    // The progress line would be garbled by the lines of verbose output and by review prompts
+    let prints_lines = verbose && matches!(output, Output::Text);
+    if !(no_progress || interactive || prints_lines) {
+        observers.push(Box::new(ProgressDisplay::new()));
     }
-    let local = local_path::files_by_remote_name(local_path.as_str(), path.as_str())?;
-    let remote = client.list_files(path.as_str(), &ignore, concurrency)?;
-    let job = plan_sync(&local, &remote, &ignore);
-    execute_sync(
-        verbose,
+    let summarizer = Arc::new(Summarizer::new(dry_run));
+    observers.push(Box::new(summarizer.clone()));
+    let target = format!("{storage_zone}/{}", path.trim_start_matches('/'));
+    let title = if dry_run {
+        format!("Dry run of thumper sync to {target}")
+    } else {
+        format!("thumper sync to {target}")
+    };
+    let github = GithubActions::from_env(title).map(Arc::new);
+    if let Some(github) = &github {
+        observers.push(Box::new(github.clone()));
+    }
+    let metrics = metrics_file
+        .as_ref()
+        .map(|_| Arc::new(Metrics::new(storage_zone.as_str(), path.as_str())));
+    if let Some(metrics) = &metrics {
+        observers.push(Box::new(metrics.clone()));
+    }
+    let notifier = match notify_url.is_empty() {
+        true => None,
+        false => {
+            let secret = notify_secret.or_else(|| env::var("THUMPER_NOTIFY_SECRET").ok());
+            let timeout = Duration::from_secs(notify_timeout);
+            Some(Arc::new(Notifier::new(notify_url, secret, timeout)?))
+        }
+    };
+    if let Some(notifier) = &notifier {
+        observers.push(Box::new(notifier.clone()));
+    }
+    let deploy = Deploy {
+        zone: storage_zone.as_str(),
+        path: path.as_str(),
         dry_run,
-        job,
-        &client,
-        lockfile.as_str(),
-        concurrency,
-    )?;
-    if !dry_run {
-        remove_lock(&client, lockfile.as_str())?;
+        git_commit: notifier
+            .as_ref()
+            .and_then(|_| git_ref.clone().or_else(previews::current_git_ref)),
+    };
+    let client = client.with_events(Events::new(observers));
+    // Every way the sync can fail from here on is notified and written to the metrics
+    let sync = || -> anyhow::Result<()> {
+        let local =
+            local_path::local_files(from_list.as_deref(), local_path.as_str(), path.as_str())?;
+        let journal = if dry_run {
+            None
+        } else {
+            let fingerprint = journal::fingerprint(&local, storage_zone.as_str(), path.as_str())?;
+            let journal_path = journal.map(PathBuf::from).unwrap_or_else(|| {
+                journal::default_path(storage_zone.as_str(), path.as_str(), local_path.as_str())
+            });
+            let earlier = match resume {
+                true => journal::interrupted(&journal_path, fingerprint.as_str())?,
+                false => None,
+            };
+            if earlier.is_some() {
+                eprintln!("Resuming interrupted sync");
+            }
+            // The journal is only replaced once the lock is ours, a sync refused by a held lock
+            // keeps the journal of the interrupted one
+            let owned = earlier.as_ref().and_then(journal::Interrupted::lock);
+            let lock = take_lock(&client, lockfile.as_str(), force, owned)?;
+            Some(Journal::open(
+                journal_path,
+                fingerprint.as_str(),
+                lock.as_str(),
+                earlier,
+            )?)
+        };
+        let remote = client.list_files(path.as_str(), &ignore, concurrency)?;
+        let job = plan_sync(&local, &remote.files, &ignore, remote.is_complete());
+        let job = match &journal {
+            Some(journal) => journal.pending(job),
+            None => job,
+        };
+        let empty_directories = plan_empty_directories(&local, &remote, &ignore);
+        let remote_files = remote
+            .files
+            .keys()
+            .filter(|name| name.as_str() != lockfile)
+            .count();
+        let reviewed =
+            review_if_interactive(interactive, job, empty_directories, lockfile.as_str()).and_then(
+                |(job, empty_directories)| {
+                    enforce_delete_limits(&delete_limits, &job, remote_files, lockfile.as_str())?;
+                    Ok((job, empty_directories))
+                },
+            );
+        let (job, empty_directories) = match reviewed {
+            Ok(reviewed) => reviewed,
+            Err(e) => {
+                if !dry_run {
+                    remove_lock(&client, lockfile.as_str())?;
+                }
+                return Err(e);
+            }
+        };
+        client.events().emit(plan_ready(&job, &empty_directories));
+        let trash = trash.then(|| trash::trash_prefix(deploy_id.as_str()));
+        let options = ExecuteOptions {
+            dry_run,
+            // Diffs would break up the lines of JSON
+            diff: (diff && matches!(output, Output::Text)).then_some(diff_max_bytes),
+            trash: trash.as_deref(),
+            trashed: None,
+            history: history.as_ref(),
+            journal: journal.as_ref(),
+            keep_going,
+        };
+        let synced = execute_sync(options, job, &client, lockfile.as_str(), concurrency)
+            .and_then(|()| delete_directories(dry_run, &empty_directories, &client));
+        // Deletes below directories that could not be listed were skipped, which the sync must not
+        // hide behind a success
+        let incomplete = remote.ensure_complete().context("Skipped deletes").err();
+        summarizer.print(summary);
+        // The deploy is done by now, failing to report it must not fail it and keep the lock
+        if let Some(github) = github
+            && let Err(e) = github.finish()
+        {
+            warn!("Unable to write the GitHub Actions step summary: {e}");
+        }
+        synced?;
+        if let Some(name) = preview
+            && !dry_run
+        {
+            let git_ref = git_ref.or_else(previews::current_git_ref);
+            previews::write_marker(&client, path.as_str(), name.as_str(), git_ref, Utc::now())?;
+        }
+        if let Some(journal) = journal {
+            journal.finish()?;
+        }
+        if !dry_run {
+            remove_lock(&client, lockfile.as_str())?;
+        }
+        incomplete.map_or(Ok(()), Err)
+    };
+    let synced = sync();
+    if let Some(notifier) = &notifier {
+        notifier.notify(&deploy, summarizer.summary(), synced.as_ref().err());
     }
-    Ok(())
+    if let (Some(metrics), Some(file)) = (&metrics, &metrics_file)
+        && let Err(e) = metrics.write(Path::new(file), synced.is_ok())
+    {
+        warn!("Unable to write metrics to {file}: {e}");
+    }
+    synced
 }
 
 fn use_api_key(api_key: Option<String>) -> anyhow::Result<String> {
@@ -184,11 +801,70 @@ fn use_api_key(api_key: Option<String>) -> anyhow::Result<String> {
         .context("No API key provided with --api-key or thumper_API_KEY")
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Cli::parse();
+/// Parse the command line, reporting invalid arguments like any other error in the
+/// `--error-format` they ask for. clap would exit with 2, which `thumper check` exits with when
+/// uploads are pending.
+fn parse_args() -> Cli {
+    match Cli::try_parse() {
+        Ok(args) => args,
+        // --help and --version
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            let format = ErrorFormat::from_args(
+                env::args_os().map(|arg| arg.to_string_lossy().into_owned()),
+            );
+            process::exit(error::report(&error::usage(&e), format))
+        }
+    }
+}
+
+fn main() {
+    let args = parse_args();
+    logging::init(logging::level(args.quiet, args.verbose), args.log_format);
+    if let Err(e) = run(args.command, args.verbose > 0) {
+        if github::detected() {
+            github::annotate(&e);
+        }
+        process::exit(error::report(&e, args.error_format));
+    }
+}
 
-    match args.command {
-        Action::Sync { args } => do_sync(args),
+fn run(command: Action, verbose: bool) -> anyhow::Result<()> {
+    match command {
+        Action::Sync { mut args } => {
+            args.verbose = verbose;
+            do_sync(*args)
+        }
+        Action::Check { args } => {
+            let code = check::do_check(args)?;
+            if code != 0 {
+                process::exit(code);
+            }
+            Ok(())
+        }
+        Action::Plan { args } => plan::do_plan(args),
+        Action::Apply { mut args } => {
+            args.verbose = verbose;
+            plan::do_apply(args)
+        }
+        Action::Pull { mut args } => {
+            args.verbose = verbose;
+            pull::do_pull(args)
+        }
+        Action::Ls { args } => files::do_ls(args),
+        Action::Cat { args } => files::do_cat(args),
+        Action::Put { args } => files::do_put(args),
+        Action::Rm { args } => files::do_rm(args),
+        Action::Mv { args } => files::do_mv(args),
+        Action::Trash { command } => trash::do_trash(command),
+        Action::Previews { command } => previews::do_previews(command),
+        Action::History { args } => history::do_history(args),
+        Action::Restore { args } => history::do_restore(args),
+        Action::Release { mut args } => {
+            args.verbose = verbose;
+            release::do_release(args)
+        }
+        Action::Rollback { args } => release::do_rollback(args),
         Action::Completions { shell } => {
             let sh = match shell.as_str() {
                 "bash" => Ok(Bash),
@@ -206,14 +882,12 @@ fn main() -> anyhow::Result<()> {
             let key = use_api_key(api_key)?;
             let client = reqwest::blocking::Client::new();
             let encoded = urlencoding::encode(url.as_str());
-            let response = client
+            let request = client
                 .post("https://api.bunny.net/purge")
-                .query(&[("url", encoded.as_ref())])
-                .header("AccessKey", key.as_str())
-                .send()?;
-            Ok(response
-                .error_for_status()
-                .map(|_| println!("Purged {url}"))?)
+                .query(&[("url", encoded.as_ref())]);
+            api::send(request, key.as_str(), url.as_str())?;
+            println!("Purged {url}");
+            Ok(())
         }
         Action::PurgeZone {
             pullzone,
@@ -222,21 +896,175 @@ fn main() -> anyhow::Result<()> {
         } => {
             let key = use_api_key(api_key)?;
             let client = reqwest::blocking::Client::new();
-            let request = client
-                .post(format!(
-                    "https://api.bunny.net/pullzone/{pullzone}/purgeCache"
-                ))
-                .header("AccessKey", key);
-            let response = if let Some(tag) = cache_tag {
+            let url = format!("https://api.bunny.net/pullzone/{pullzone}/purgeCache");
+            let mut request = client.post(url.as_str());
+            if let Some(tag) = cache_tag {
                 let mut form = FxHashMap::default();
                 form.insert("CacheTag", tag);
-                request.form(&form).send()
-            } else {
-                request.send()
-            }?;
-            Ok(response
-                .error_for_status()
-                .map(|_| println!("Purged {pullzone}"))?)
+                request = request.form(&form);
+            }
+            api::send(request, key.as_str(), url.as_str())?;
+            println!("Purged {pullzone}");
+            Ok(())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeStorageZone, FakeWebhook, TempDir};
+
+    /// The arguments of `thumper sync src <zone>` against the fake `zone`, with `options`
+    fn sync_args(zone: &FakeStorageZone, name: &str, options: &[&str]) -> SyncArgs {
+        let endpoint = zone.endpoint();
+        let mut args = vec![
+            "thumper",
+            "sync",
+            "src",
+            name,
+            "--endpoint",
+            endpoint.as_str(),
+        ];
+        args.extend([
+            "--access-key",
+            "secret",
+            "--no-progress",
+            "--summary",
+            "none",
+        ]);
+        args.extend(options);
+        match Cli::try_parse_from(args).unwrap().command {
+            Action::Sync { args } => *args,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn writes_failed_metrics_when_the_lock_is_held() {
+        let zone = FakeStorageZone::start("metrics-lock-zone");
+        zone.insert(".thumper.lock", b"2026-10-18T12:00:00+00:00");
+        let dir = TempDir::new("lock-metrics");
+        let file = dir.join("thumper.prom");
+        let file_arg = file.to_string_lossy().into_owned();
+        let args = sync_args(&zone, "metrics-lock-zone", &["--metrics-file", &file_arg]);
+
+        let e = do_sync(args).unwrap_err();
+        assert!(matches!(error::find(&e), Some(Error::LockHeld { .. })));
+        let metrics = fs::read_to_string(&file).unwrap();
+        assert!(
+            metrics
+                .lines()
+                .any(|line| line.starts_with("thumper_sync_success{") && line.ends_with("} 0")),
+            "{metrics}"
+        );
+    }
+
+    #[test]
+    fn notifies_failure_before_the_sync_started() {
+        let zone = FakeStorageZone::start("notify-zone");
+        let hook = FakeWebhook::start(&[]);
+        let url = hook.url();
+        let options = [
+            "--notify-url",
+            url.as_str(),
+            "--from-list",
+            "missing-list.txt",
+        ];
+
+        do_sync(sync_args(&zone, "notify-zone", &options)).unwrap_err();
+        let received = hook.received();
+        assert_eq!(received.len(), 1);
+        let payload: serde_json::Value = serde_json::from_slice(&received[0].body).unwrap();
+        assert_eq!(payload["success"], false);
+        assert!(zone.files().is_empty());
+    }
+
+    #[test]
+    fn leaves_previews_alone_when_syncing_the_root() {
+        let zone = FakeStorageZone::start("previews-root-zone");
+        zone.insert("previews/feature/index.html", b"preview");
+        zone.insert("previews/feature/.thumper-preview.json", b"{}");
+        zone.insert("old.html", b"old");
+
+        do_sync(sync_args(
+            &zone,
+            "previews-root-zone",
+            &["--allow-mass-delete"],
+        ))
+        .unwrap();
+        let files = zone.files();
+        assert!(files.contains(&"previews/feature/index.html".to_string()));
+        assert!(files.contains(&"previews/feature/.thumper-preview.json".to_string()));
+        assert!(files.contains(&"main.rs".to_string()));
+        assert!(!files.contains(&"old.html".to_string()));
+    }
+
+    #[test]
+    fn keeps_the_error_of_the_command_when_unlocking_fails() {
+        let zone = FakeStorageZone::start("unlock-zone");
+        zone.fail(".thumper.lock", "503 Service Unavailable");
+        let client = zone.client();
+
+        let failed = unlock(
+            &client,
+            ".thumper.lock",
+            Err::<(), _>(anyhow!("Apply failed")),
+        );
+        assert_eq!(failed.unwrap_err().to_string(), "Apply failed");
+        let unlocked = unlock(&client, ".thumper.lock", Ok(()));
+        assert!(unlocked.unwrap_err().to_string().contains(".thumper.lock"));
+    }
+
+    #[test]
+    fn refuses_held_lock_with_its_own_exit_code() {
+        let zone = FakeStorageZone::start("lock-zone");
+        let client = zone.client();
+        let lock = take_lock(&client, ".thumper.lock", false, None).unwrap();
+
+        let e = take_lock(&client, ".thumper.lock", false, Some("someone else")).unwrap_err();
+        assert!(matches!(error::find(&e), Some(Error::LockHeld { .. })));
+        assert_eq!(error::report(&e, error::ErrorFormat::Json), 10);
+        let lock = take_lock(&client, ".thumper.lock", false, Some(lock.as_str())).unwrap();
+        take_lock(&client, ".thumper.lock", true, Some("someone else")).unwrap();
+        assert_ne!(zone.get(".thumper.lock").unwrap(), lock.as_bytes());
+        remove_lock(&client, ".thumper.lock").unwrap();
+        assert!(zone.files().is_empty());
+    }
+
+    #[test]
+    fn keeps_going_and_skips_deletes_after_failed_uploads() {
+        let zone = FakeStorageZone::start("keep-going-zone");
+        zone.insert("old.html", b"old");
+        zone.fail("broken.css", "503 Service Unavailable");
+        let dir = TempDir::new("keep-going");
+        for name in ["a.css", "broken.css", "index.html"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let put = |name: &str| SyncPlan::Put {
+            local: dir.join(name).into(),
+            remote: name.to_string(),
+        };
+        let job = vec![
+            put("a.css"),
+            put("broken.css"),
+            put("missing.css"),
+            put("index.html"),
+            SyncPlan::Delete {
+                remote: "old.html".into(),
+            },
+        ];
+        let options = ExecuteOptions {
+            keep_going: true,
+            ..Default::default()
+        };
+
+        let err = execute_sync(options, job, &zone.client(), ".thumper.lock", 1).unwrap_err();
+        let message = err.to_string();
+        assert!(message.starts_with("Sync failed, 2 of 5 files done, 2 failed:"));
+        assert!(message.contains("\n  HTTP 503 Service Unavailable (1):\n    broken.css: "));
+        assert!(message.contains("\n  I/O: entity not found (1):\n    missing.css: "));
+        assert!(message.ends_with("\nSkipped 1 deletes because uploads failed"));
+        assert_eq!(zone.files(), vec!["a.css", "index.html", "old.html"]);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::events::{Event, Observer};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+/// Upper bounds in seconds of the buckets of the request latency histogram
+const LATENCY_BUCKETS: [f64; 9] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Gathers metrics of a sync to write for the node_exporter textfile collector
+pub struct Metrics {
+    zone: String,
+    path: String,
+    started: Instant,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Files and bytes by action
+    files: BTreeMap<String, (u64, u64)>,
+    /// Latencies in seconds by operation
+    latencies: BTreeMap<String, Histogram>,
+    /// Failed requests by operation and status, `network` when bunny.net was not reached
+    errors: BTreeMap<(String, String), u64>,
+    retries: u64,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+impl Metrics {
+    pub fn new(zone: &str, path: &str) -> Self {
+        Metrics {
+            zone: zone.to_string(),
+            path: path.to_string(),
+            started: Instant::now(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Write the metrics to `file`, through a temporary file so that the collector never reads
+    /// half of them
+    pub fn write(&self, file: &Path, success: bool) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let text = self.render(success, self.started.elapsed().as_secs_f64(), timestamp);
+        let mut temporary = file.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, text)?;
+        fs::rename(&temporary, file)
+    }
+
+    fn render(&self, success: bool, duration: f64, timestamp: u64) -> String {
+        let Ok(state) = self.state.lock() else {
+            return String::new();
+        };
+        let labels = format!(
+            "zone=\"{}\",path=\"{}\"",
+            escape(self.zone.as_str()),
+            escape(self.path.as_str())
+        );
+        let mut text = String::new();
+
+        header(
+            &mut text,
+            "files_total",
+            "counter",
+            "Files synced by action",
+        );
+        for (action, (files, _)) in &state.files {
+            let _ = writeln!(
+                text,
+                "thumper_files_total{{{labels},action=\"{action}\"}} {files}"
+            );
+        }
+        header(
+            &mut text,
+            "bytes_total",
+            "counter",
+            "Size of the local files synced by action",
+        );
+        for (action, (_, bytes)) in &state.files {
+            let _ = writeln!(
+                text,
+                "thumper_bytes_total{{{labels},action=\"{action}\"}} {bytes}"
+            );
+        }
+        header(
+            &mut text,
+            "request_duration_seconds",
+            "histogram",
+            "Latency of requests to the storage zone by operation",
+        );
+        for (operation, histogram) in &state.latencies {
+            let series = format!("{labels},operation=\"{operation}\"");
+            for (count, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    text,
+                    "thumper_request_duration_seconds_bucket{{{series},le=\"{bound}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                text,
+                "thumper_request_duration_seconds_bucket{{{series},le=\"+Inf\"}} {}\n\
+                 thumper_request_duration_seconds_sum{{{series}}} {}\n\
+                 thumper_request_duration_seconds_count{{{series}}} {}",
+                histogram.count, histogram.sum, histogram.count
+            );
+        }
+        header(
+            &mut text,
+            "request_errors_total",
+            "counter",
+            "Failed requests to the storage zone by operation and HTTP status",
+        );
+        for ((operation, status), errors) in &state.errors {
+            let series = format!("{labels},operation=\"{operation}\",status=\"{status}\"");
+            let _ = writeln!(text, "thumper_request_errors_total{{{series}}} {errors}");
+        }
+        header(&mut text, "retries_total", "counter", "Retried listings");
+        let _ = writeln!(text, "thumper_retries_total{{{labels}}} {}", state.retries);
+        header(
+            &mut text,
+            "sync_duration_seconds",
+            "gauge",
+            "Duration of the last sync",
+        );
+        let _ = writeln!(text, "thumper_sync_duration_seconds{{{labels}}} {duration}");
+        header(
+            &mut text,
+            "sync_success",
+            "gauge",
+            "Whether the last sync succeeded",
+        );
+        let _ = writeln!(
+            text,
+            "thumper_sync_success{{{labels}}} {}",
+            u8::from(success)
+        );
+        header(
+            &mut text,
+            "sync_timestamp_seconds",
+            "gauge",
+            "When the last sync finished, in seconds since the Unix epoch",
+        );
+        let _ = writeln!(
+            text,
+            "thumper_sync_timestamp_seconds{{{labels}}} {timestamp}"
+        );
+        text
+    }
+}
+
+impl Observer for Metrics {
+    fn observe(&self, event: &Event) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        match event {
+            Event::Job {
+                action,
+                size,
+                dry_run: false,
+                ..
+            } => {
+                let (files, bytes) = state.files.entry(action.to_string()).or_default();
+                *files += 1;
+                *bytes += size;
+            }
+            Event::Request {
+                operation,
+                status,
+                duration_ms,
+                ..
+            } => {
+                state
+                    .latencies
+                    .entry(operation.to_string())
+                    .or_default()
+                    .observe(*duration_ms as f64 / 1000.0);
+                let failed = match status {
+                    Some(status) if (200..300).contains(status) => None,
+                    // No lockfile is what taking the lock hopes to find
+                    Some(404) if *operation == "lock" => None,
+                    Some(status) => Some(status.to_string()),
+                    None => Some("network".to_string()),
+                };
+                if let Some(status) = failed {
+                    *state
+                        .errors
+                        .entry((operation.to_string(), status))
+                        .or_default() += 1;
+                }
+            }
+            Event::ListRetry { .. } => state.retries += 1,
+            _ => {}
+        }
+    }
+}
+
+fn header(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(
+        text,
+        "# HELP thumper_{name} {help}\n# TYPE thumper_{name} {kind}"
+    );
+}
+
+/// Escape a label value of the text exposition format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planning::Reason;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn renders_counters_and_histograms() {
+        let metrics = Metrics::new("zone", "site/\"docs\"");
+        let request = |operation, status, duration_ms| Event::Request {
+            operation,
+            path: "site/",
+            status,
+            duration_ms,
+            retries: 0,
+        };
+        for event in [
+            request("list", Some(200), 40),
+            request("put", Some(201), 300),
+            request("put", Some(503), 2000),
+            request("delete", None, 5),
+            request("lock", Some(404), 20),
+            Event::ListRetry {
+                path: "site/",
+                attempt: 2,
+                error: "timeout".into(),
+            },
+            Event::Job {
+                path: "site/index.html",
+                action: "put",
+                reason: Reason::NewFile,
+                bytes: 2048,
+                size: 2048,
+                hash_ms: 0,
+                duration_ms: 300,
+                dry_run: false,
+            },
+        ] {
+            metrics.observe(&event);
+        }
+        let text = metrics.render(false, 2.5, 1700000000);
+        let labels = "zone=\"zone\",path=\"site/\\\"docs\\\"\"";
+        for line in [
+            "# TYPE thumper_files_total counter".to_string(),
+            format!("thumper_files_total{{{labels},action=\"put\"}} 1"),
+            format!("thumper_bytes_total{{{labels},action=\"put\"}} 2048"),
+            format!(
+                "thumper_request_duration_seconds_bucket{{{labels},operation=\"put\",le=\"0.5\"}} 1"
+            ),
+            format!(
+                "thumper_request_duration_seconds_bucket{{{labels},operation=\"put\",le=\"+Inf\"}} 2"
+            ),
+            format!("thumper_request_duration_seconds_sum{{{labels},operation=\"put\"}} 2.3"),
+            format!("thumper_request_errors_total{{{labels},operation=\"put\",status=\"503\"}} 1"),
+            format!(
+                "thumper_request_errors_total{{{labels},operation=\"delete\",status=\"network\"}} 1"
+            ),
+            format!("thumper_retries_total{{{labels}}} 1"),
+            format!("thumper_sync_duration_seconds{{{labels}}} 2.5"),
+            format!("thumper_sync_success{{{labels}}} 0"),
+        ] {
+            assert!(text.lines().any(|l| l == line), "{line} missing in\n{text}");
+        }
+        assert!(!text.contains("operation=\"list\",status"));
+        assert!(!text.contains("operation=\"lock\",status"));
+
+        let dir = TempDir::new("metrics");
+        let file = dir.join("thumper.prom");
+        metrics.write(&file, true).unwrap();
+        let written = fs::read_to_string(&file).unwrap();
+        assert!(written.contains(format!("thumper_sync_success{{{labels}}} 1").as_str()));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::events::{Event, Observer};
+use crate::logging;
+use crate::summary::DeploySummary;
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use log::warn;
+use reqwest::blocking::Client;
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// How many changed paths a notification lists
+const MAX_CHANGED: usize = 100;
+/// How often a notification is tried before giving up on a URL
+const NOTIFY_ATTEMPTS: u32 = 3;
+/// Delay before the first retry of a notification, doubled for every further retry
+const NOTIFY_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Header with the HMAC-SHA256 of the body, when there is a secret
+pub const SIGNATURE_HEADER: &str = "X-Thumper-Signature-256";
+
+/// Where and what a sync deployed
+pub struct Deploy<'a> {
+    pub zone: &'a str,
+    pub path: &'a str,
+    pub dry_run: bool,
+    pub git_commit: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: &'static str,
+    success: bool,
+    zone: &'a str,
+    path: &'a str,
+    dry_run: bool,
+    git_commit: Option<&'a str>,
+    error: Option<String>,
+    summary: DeploySummary,
+    /// The first `MAX_CHANGED` uploaded or deleted paths
+    changed: &'a [String],
+    changed_total: usize,
+}
+
+/// POSTs a JSON payload about a finished sync to webhook URLs. Failing to notify is only logged,
+/// it never fails the sync.
+pub struct Notifier {
+    urls: Vec<String>,
+    secret: Option<String>,
+    client: Client,
+    changed: Mutex<Vec<String>>,
+}
+
+impl Notifier {
+    pub fn new(
+        urls: Vec<String>,
+        secret: Option<String>,
+        timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        if let Some(secret) = &secret {
+            logging::redact(secret);
+        }
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("Unable to set up the HTTP client for notifications")?;
+        Ok(Notifier {
+            urls,
+            secret,
+            client,
+            changed: Mutex::new(vec![]),
+        })
+    }
+
+    /// Notify every URL that the sync of `deploy` finished, failing with `error` if it did
+    pub fn notify(&self, deploy: &Deploy, summary: DeploySummary, error: Option<&anyhow::Error>) {
+        let changed = match self.changed.lock() {
+            Ok(changed) => changed.clone(),
+            Err(_) => vec![],
+        };
+        let payload = Payload {
+            event: "sync",
+            success: error.is_none(),
+            zone: deploy.zone,
+            path: deploy.path,
+            dry_run: deploy.dry_run,
+            git_commit: deploy.git_commit.as_deref(),
+            error: error.map(|e| format!("{e:#}")),
+            summary,
+            changed: &changed[..changed.len().min(MAX_CHANGED)],
+            changed_total: changed.len(),
+        };
+        let Ok(body) = serde_json::to_vec(&payload) else {
+            return;
+        };
+        for url in &self.urls {
+            if let Err(e) = self.post(url.as_str(), &body) {
+                warn!("Unable to notify {url}: {e}");
+            }
+        }
+    }
+
+    /// POST `body` to `url`, retrying server errors and network failures
+    fn post(&self, url: &str, body: &[u8]) -> Result<(), String> {
+        let mut attempt = 1;
+        loop {
+            let mut request = self
+                .client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .body(body.to_vec());
+            if let Some(secret) = &self.secret {
+                request = request.header(SIGNATURE_HEADER, signature(secret, body));
+            }
+            let error = match request.send() {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    if !(status.is_server_error() || status.as_u16() == 429) {
+                        return Err(format!("HTTP {status}"));
+                    }
+                    format!("HTTP {status}")
+                }
+                Err(e) => format!("{e}"),
+            };
+            if attempt == NOTIFY_ATTEMPTS {
+                return Err(error);
+            }
+            warn!("Notifying {url} failed with {error}, trying again");
+            thread::sleep(NOTIFY_RETRY_DELAY * 2u32.pow(attempt - 1));
+            attempt += 1;
+        }
+    }
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `body` with `secret`
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return String::new();
+    };
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+impl Observer for Notifier {
+    fn observe(&self, event: &Event) {
+        if let Event::Job { path, action, .. } = event
+            && matches!(*action, "put" | "delete" | "trash")
+            && let Ok(mut changed) = self.changed.lock()
+        {
+            changed.push(path.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planning::Reason;
+    use crate::testing::FakeWebhook;
+    use anyhow::anyhow;
+
+    fn deploy() -> Deploy<'static> {
+        Deploy {
+            zone: "zone",
+            path: "site/",
+            dry_run: false,
+            git_commit: Some("abc123".into()),
+        }
+    }
+
+    #[test]
+    fn posts_signed_payload_and_retries_server_errors() {
+        let hook = FakeWebhook::start(&["503 Service Unavailable"]);
+        let notifier = Notifier::new(
+            vec![hook.url()],
+            Some("hook-secret".into()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        notifier.observe(&Event::Job {
+            path: "site/index.html",
+            action: "put",
+            reason: Reason::NewFile,
+            bytes: 10,
+            size: 10,
+            hash_ms: 0,
+            duration_ms: 1,
+            dry_run: false,
+        });
+        notifier.notify(&deploy(), DeploySummary::default(), None);
+
+        let received = hook.received();
+        assert_eq!(received.len(), 2);
+        let request = &received[1];
+        assert_eq!(
+            request.headers.get("x-thumper-signature-256"),
+            Some(&signature("hook-secret", &request.body))
+        );
+        let payload: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(payload["success"], true);
+        assert_eq!(payload["git_commit"], "abc123");
+        assert_eq!(payload["changed"], serde_json::json!(["site/index.html"]));
+        assert_eq!(payload["changed_total"], 1);
+        assert_eq!(payload["summary"]["uploaded"], 0);
+    }
+
+    #[test]
+    fn reports_failure_and_gives_up_on_client_errors() {
+        let hook = FakeWebhook::start(&["400 Bad Request"]);
+        let notifier = Notifier::new(vec![hook.url()], None, Duration::from_secs(5)).unwrap();
+        let error = anyhow!("Sync failed");
+        notifier.notify(&deploy(), DeploySummary::default(), Some(&error));
+
+        let received = hook.received();
+        assert_eq!(received.len(), 1);
+        assert!(!received[0].headers.contains_key("x-thumper-signature-256"));
+        let payload: serde_json::Value = serde_json::from_slice(&received[0].body).unwrap();
+        assert_eq!(payload["success"], false);
+        assert_eq!(payload["error"], "Sync failed");
+    }
+
+    #[test]
+    fn signs_like_other_hmac_sha256_implementations() {
+        assert_eq!(
+            signature("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
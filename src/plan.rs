@@ -0,0 +1,384 @@
+use crate::api::{RemoteListing, StorageZoneClient};
+use crate::check::report_changes;
+use crate::cli::{ApplyArgs, DeleteLimitArgs, PlanArgs};
+use crate::error::Error;
+use crate::events::{Events, Output};
+use crate::history::HistoryPolicy;
+use crate::local_path::{self, LocalFile};
+use crate::planning::{
+    Execution, Reason, SyncPlan, plan_empty_directories, plan_execution, plan_sync,
+};
+use crate::{
+    ExecuteOptions, SyncJob, delete_directories, enforce_delete_limits, execute_sync, init_client,
+    init_sync, take_lock, trash, unlock, with_reserved_prefixes,
+};
+use anyhow::{Context, anyhow};
+use chrono::Local;
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+
+const PLAN_VERSION: u32 = 1;
+
+/// A sync plan that can be reviewed and applied later with `thumper apply`.
+///
+/// Local paths in the plan are relative to the working directory `thumper plan` ran in.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedPlan {
+    version: u32,
+    created: String,
+    endpoint: String,
+    storage_zone: String,
+    path: String,
+    ignore: Vec<String>,
+    lockfile: String,
+    /// The checksum of every file in the storage zone path when the plan was made
+    remote: BTreeMap<String, Option<String>>,
+    job: Vec<SyncPlan>,
+    /// Why each file in the job is uploaded, skipped or deleted
+    #[serde(default)]
+    reasons: BTreeMap<String, Reason>,
+    empty_directories: Vec<String>,
+}
+
+fn checksum_of(local: &LocalFile) -> anyhow::Result<[u8; 32]> {
+    let content = fs::read(&local.path)
+        .with_context(|| format!("Unable to read {}", local.path.display()))?;
+    Ok(Sha256::digest(&content).into())
+}
+
+fn observed_checksums(listing: &RemoteListing, lockfile: &str) -> BTreeMap<String, Option<String>> {
+    listing
+        .files
+        .iter()
+        .filter(|(name, _)| name.as_str() != lockfile)
+        .map(|(name, meta)| (name.clone(), meta.checksum.map(hex::encode_upper)))
+        .collect()
+}
+
+impl SavedPlan {
+    fn create(
+        local: &FxHashMap<String, LocalFile>,
+        remote: &RemoteListing,
+        endpoint: String,
+        storage_zone: String,
+        path: String,
+        ignore: Vec<String>,
+        lockfile: String,
+    ) -> anyhow::Result<Self> {
+        let mut job = plan_sync(local, &remote.files, &ignore, remote.is_complete());
+        for plan in job.iter_mut() {
+            if let SyncPlan::Put { local, .. } | SyncPlan::Replace { local, .. } = plan {
+                local.checksum = Some(checksum_of(local)?);
+            }
+        }
+        let mut reasons = BTreeMap::new();
+        for plan in &job {
+            let Execution { remote, reason, .. } = plan_execution(plan, fs::read)?;
+            reasons.insert(remote.to_string(), reason);
+        }
+        Ok(SavedPlan {
+            version: PLAN_VERSION,
+            created: Local::now().to_rfc3339(),
+            endpoint,
+            storage_zone,
+            empty_directories: plan_empty_directories(local, remote, &ignore),
+            remote: observed_checksums(remote, lockfile.as_str()),
+            path,
+            ignore,
+            lockfile,
+            job,
+            reasons,
+        })
+    }
+
+    /// Remote paths of local files that changed since the plan was made
+    fn changed_local_files(&self) -> Vec<&str> {
+        self.job
+            .iter()
+            .filter(|plan| match plan {
+                SyncPlan::Put { local, .. } | SyncPlan::Replace { local, .. } => {
+                    checksum_of(local).ok() != local.checksum
+                }
+                SyncPlan::Delete { .. } | SyncPlan::Keep { .. } => false,
+            })
+            .map(|plan| plan.remote())
+            .collect()
+    }
+
+    /// Remote paths that were added, removed or changed since the plan was made
+    fn changed_remote_files(&self, listing: &RemoteListing) -> Vec<String> {
+        let current = observed_checksums(listing, self.lockfile.as_str());
+        let mut changed: Vec<String> = self
+            .remote
+            .iter()
+            .filter(|(name, checksum)| current.get(*name) != Some(*checksum))
+            .map(|(name, _)| name.clone())
+            .chain(
+                current
+                    .keys()
+                    .filter(|name| !self.remote.contains_key(*name))
+                    .cloned(),
+            )
+            .collect();
+        changed.sort();
+        changed
+    }
+}
+
+fn refuse(what: &str, changed: &[impl AsRef<str>]) -> anyhow::Result<()> {
+    if changed.is_empty() {
+        Ok(())
+    } else {
+        let changed: Vec<&str> = changed.iter().map(|name| name.as_ref()).collect();
+        Err(Error::SafetyLimit(format!(
+            "Refusing to apply plan, {} {what} changed since it was made: {}",
+            changed.len(),
+            changed.join(", ")
+        ))
+        .into())
+    }
+}
+
+pub fn do_plan(args: PlanArgs) -> anyhow::Result<()> {
+    let PlanArgs {
+        endpoint,
+        access_key,
+        local_path,
+        storage_zone,
+        path,
+        lockfile,
+        ignore,
+        concurrency,
+        from_list,
+        output,
+    } = args;
+    let ignore = with_reserved_prefixes(ignore);
+    let concurrency = concurrency.unwrap_or_else(num_cpus::get);
+
+    let SyncJob {
+        client,
+        path,
+        local_path,
+    } = init_sync(
+        access_key,
+        local_path,
+        path,
+        storage_zone.clone(),
+        endpoint.clone(),
+    )?;
+    let local = local_path::local_files(from_list.as_deref(), local_path.as_str(), path.as_str())?;
+    let remote = client.list_files(path.as_str(), &ignore, concurrency)?;
+    let plan = SavedPlan::create(
+        &local,
+        &remote,
+        endpoint,
+        storage_zone,
+        path,
+        ignore,
+        lockfile,
+    )?;
+    report_changes(&plan.job, &plan.empty_directories, plan.lockfile.as_str())?;
+    fs::write(&output, serde_json::to_string_pretty(&plan)?)
+        .with_context(|| format!("Unable to write {output}"))?;
+    eprintln!("Saved plan to {output}");
+    Ok(())
+}
+
+fn apply_plan(
+    client: &StorageZoneClient,
+    plan: SavedPlan,
+    options: ExecuteOptions,
+    concurrency: usize,
+    delete_limits: &DeleteLimitArgs,
+) -> anyhow::Result<()> {
+    let remote = client.list_files(plan.path.as_str(), &plan.ignore, concurrency)?;
+    if !remote.is_complete() {
+        return Err(Error::SafetyLimit(format!(
+            "Refusing to apply plan, unable to list {} to verify it",
+            remote.unlisted.join(", ")
+        ))
+        .into());
+    }
+    refuse("remote files", &plan.changed_remote_files(&remote))?;
+    enforce_delete_limits(
+        delete_limits,
+        &plan.job,
+        plan.remote.len(),
+        plan.lockfile.as_str(),
+    )?;
+    execute_sync(
+        options,
+        plan.job,
+        client,
+        plan.lockfile.as_str(),
+        concurrency,
+    )?;
+    delete_directories(options.dry_run, &plan.empty_directories, client)
+}
+
+pub fn do_apply(args: ApplyArgs) -> anyhow::Result<()> {
+    let ApplyArgs {
+        plan,
+        access_key,
+        dry_run,
+        force,
+        verbose,
+        concurrency,
+        delete_limits,
+        trash,
+        history,
+        keep_going,
+    } = args;
+    let deploy_id = trash::deploy_id();
+    let history = HistoryPolicy::from_args(history, deploy_id.as_str())?;
+    let concurrency = concurrency.unwrap_or_else(num_cpus::get);
+    let plan: SavedPlan = serde_json::from_str(
+        &fs::read_to_string(&plan).with_context(|| format!("Unable to read {plan}"))?,
+    )
+    .with_context(|| format!("Unable to parse {plan}"))?;
+    if plan.version != PLAN_VERSION {
+        return Err(anyhow!("Unsupported plan version {}", plan.version));
+    }
+    refuse("local files", &plan.changed_local_files())?;
+
+    let client = init_client(access_key, plan.storage_zone.clone(), plan.endpoint.clone())?
+        .with_events(Events::printing(Output::Text, verbose || dry_run));
+    let lockfile = plan.lockfile.clone();
+    if !dry_run {
+        take_lock(&client, lockfile.as_str(), force, None)?;
+    }
+    let trash = trash.then(|| trash::trash_prefix(deploy_id.as_str()));
+    let options = ExecuteOptions {
+        dry_run,
+        diff: None,
+        trash: trash.as_deref(),
+        trashed: None,
+        history: history.as_ref(),
+        journal: None,
+        keep_going,
+    };
+    let result = apply_plan(&client, plan, options, concurrency, &delete_limits);
+    if dry_run {
+        return result;
+    }
+    unlock(&client, lockfile.as_str(), result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeStorageZone, TempDir};
+    use std::path::Path;
+
+    fn site(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        fs::write(dir.join("site.css"), "body {}").unwrap();
+        dir
+    }
+
+    fn plan_for(zone: &FakeStorageZone, dir: &Path) -> SavedPlan {
+        let client = zone.client();
+        let local = local_path::files_by_remote_name(dir.to_str().unwrap(), "/").unwrap();
+        let remote = client.list_files("", &[], 2).unwrap();
+        let plan = SavedPlan::create(
+            &local,
+            &remote,
+            "unused".into(),
+            "unused".into(),
+            String::new(),
+            vec![],
+            ".thumper.lock".into(),
+        )
+        .unwrap();
+        // Round trip through the file format
+        serde_json::from_str(&serde_json::to_string(&plan).unwrap()).unwrap()
+    }
+
+    fn limits() -> DeleteLimitArgs {
+        DeleteLimitArgs {
+            max_delete: 1000,
+            max_delete_percent: 50,
+            allow_mass_delete: false,
+        }
+    }
+
+    #[test]
+    fn applies_saved_plan() {
+        let zone = FakeStorageZone::start("apply-zone");
+        zone.insert("old.html", b"old");
+        zone.insert("site.css", b"body {}");
+        let dir = site("apply");
+        let plan = plan_for(&zone, &dir);
+        assert!(plan.changed_local_files().is_empty());
+        assert_eq!(plan.reasons["old.html"], Reason::MissingLocally);
+        assert_eq!(plan.reasons["index.html"], Reason::NewFile);
+        assert_eq!(plan.reasons["site.css"], Reason::Unchanged);
+
+        apply_plan(
+            &zone.client(),
+            plan,
+            ExecuteOptions::default(),
+            2,
+            &limits(),
+        )
+        .unwrap();
+        assert_eq!(zone.files(), vec!["index.html", "site.css"]);
+    }
+
+    #[test]
+    fn refuses_when_local_files_changed() {
+        let zone = FakeStorageZone::start("apply-local-zone");
+        let dir = site("apply-local");
+        let plan = plan_for(&zone, &dir);
+        fs::write(dir.join("site.css"), "body { color: red; }").unwrap();
+        assert_eq!(plan.changed_local_files(), vec!["site.css"]);
+    }
+
+    #[test]
+    fn refuses_when_remote_files_changed() {
+        let zone = FakeStorageZone::start("apply-remote-zone");
+        zone.insert("old.html", b"old");
+        zone.insert("site.css", b"body {}");
+        let dir = site("apply-remote");
+        let plan = plan_for(&zone, &dir);
+        zone.insert("site.css", b"body { color: blue; }");
+        zone.insert("new.html", b"new");
+
+        let err = apply_plan(
+            &zone.client(),
+            plan,
+            ExecuteOptions::default(),
+            2,
+            &limits(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("new.html, site.css"));
+        assert_eq!(zone.files(), vec!["new.html", "old.html", "site.css"]);
+    }
+
+    #[test]
+    fn refuses_mass_delete() {
+        let zone = FakeStorageZone::start("apply-mass-delete-zone");
+        let old: Vec<String> = (10..22).map(|n| format!("old-{n}.html")).collect();
+        for name in &old {
+            zone.insert(name, b"old");
+        }
+        let dir = site("apply-mass-delete");
+        let plan = plan_for(&zone, &dir);
+
+        let err = apply_plan(
+            &zone.client(),
+            plan,
+            ExecuteOptions::default(),
+            2,
+            &limits(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("--allow-mass-delete"));
+        assert_eq!(zone.files(), old);
+    }
+}
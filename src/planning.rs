@@ -1,11 +1,15 @@
-use crate::api::FileMeta;
+use crate::api::{FileMeta, RemoteListing};
+use crate::error::Error;
+use crate::local_path::{LocalFile, hex_checksum};
 use fxhash::{FxHashMap, FxHashSet};
+use log::warn;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::path::PathBuf;
-use std::{fs, io};
+use std::{fmt, fs, io};
 
 fn must_remove<'a>(
-    local_files: &'a FxHashMap<String, PathBuf>,
+    local_files: &'a FxHashMap<String, LocalFile>,
     remote_files: &'a FxHashMap<String, FileMeta>,
     ignored_prefix: &[String],
 ) -> FxHashSet<&'a str> {
@@ -17,33 +21,48 @@ fn must_remove<'a>(
         .collect()
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(tag = "plan", rename_all = "snake_case")]
 pub enum SyncPlan {
     Put {
-        local: PathBuf,
+        local: LocalFile,
         remote: String,
     },
     Replace {
-        local: PathBuf,
+        local: LocalFile,
         remote: String,
+        #[serde(with = "hex_checksum")]
         remote_checksum: Option<[u8; 32]>,
+        #[serde(default)]
+        remote_content_type: Option<String>,
+        /// Size of the remote file, if known
+        #[serde(default)]
+        remote_length: Option<u64>,
     },
     Delete {
         remote: String,
     },
+    /// A remote file that is missing locally, but protected from deletion by an ignore prefix
+    Keep {
+        remote: String,
+    },
 }
 
-#[cfg(test)]
 impl SyncPlan {
-    fn remote(&self) -> &str {
+    pub fn remote(&self) -> &str {
         match self {
             SyncPlan::Put { local: _, remote } => remote.as_str(),
-            SyncPlan::Replace {
-                local: _,
-                remote,
-                remote_checksum: _,
-            } => remote.as_str(),
+            SyncPlan::Replace { remote, .. } => remote.as_str(),
             SyncPlan::Delete { remote } => remote.as_str(),
+            SyncPlan::Keep { remote } => remote.as_str(),
+        }
+    }
+
+    /// The local file that the plan uploads or compares to the remote one
+    pub fn local(&self) -> Option<&LocalFile> {
+        match self {
+            SyncPlan::Put { local, .. } | SyncPlan::Replace { local, .. } => Some(local),
+            SyncPlan::Delete { .. } | SyncPlan::Keep { .. } => None,
         }
     }
 }
@@ -52,21 +71,54 @@ impl SyncPlan {
 pub enum SyncAction {
     Put {
         content: Vec<u8>,
-        mime_type: Option<&'static str>,
+        mime_type: Option<String>,
     },
     Ignore,
     Delete,
 }
 
+/// Why `plan_execution` decided on a `SyncAction`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    NewFile,
+    ChecksumMismatch,
+    NoRemoteChecksum,
+    ContentTypeDrift,
+    Unchanged,
+    MissingLocally,
+    ProtectedByIgnore,
+    EmptyDirectory,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Reason::NewFile => "new file",
+            Reason::ChecksumMismatch => "checksum mismatch",
+            Reason::NoRemoteChecksum => "remote has no checksum",
+            Reason::ContentTypeDrift => "content-type drift",
+            Reason::Unchanged => "checksum match",
+            Reason::MissingLocally => "missing locally",
+            Reason::ProtectedByIgnore => "protected by ignore rule",
+            Reason::EmptyDirectory => "empty directory",
+        })
+    }
+}
+
 pub struct Execution<'a> {
     pub remote: &'a str,
     pub action: SyncAction,
+    pub reason: Reason,
 }
 
+/// Plan the jobs that make `remote_content` match `local`. Unless both listings are `complete`,
+/// no deletions are planned, since a file missing from an incomplete listing may well exist.
 pub fn plan_sync<'a>(
-    local: &'a FxHashMap<String, PathBuf>,
+    local: &'a FxHashMap<String, LocalFile>,
     remote_content: &'a FxHashMap<String, FileMeta>,
     ignore: &[String],
+    complete: bool,
 ) -> Vec<SyncPlan> {
     let mut job = Vec::with_capacity(local.len());
     let mut local_paths_ordered: Vec<_> = local.keys().map(|path| path.as_str()).collect();
@@ -81,6 +133,8 @@ pub fn plan_sync<'a>(
                 local: physical_path.to_owned(),
                 remote: remote_path.to_owned(),
                 remote_checksum: on_remote.checksum,
+                remote_content_type: on_remote.content_type.clone(),
+                remote_length: on_remote.length,
             });
         } else {
             job.push(SyncPlan::Put {
@@ -89,62 +143,238 @@ pub fn plan_sync<'a>(
             });
         }
     }
+    let removed = must_remove(local, remote_content, ignore);
+    if complete {
+        job.extend(removed.iter().map(|remote| SyncPlan::Delete {
+            remote: remote.to_string(),
+        }));
+    } else if !removed.is_empty() {
+        warn!(
+            "Not deleting {} files, since the listing is incomplete",
+            removed.len()
+        );
+    }
     job.extend(
-        must_remove(local, remote_content, ignore)
-            .into_iter()
-            .map(|remote| SyncPlan::Delete {
+        remote_content
+            .keys()
+            .filter(|remote| !local.contains_key(*remote) && !removed.contains(remote.as_str()))
+            .map(|remote| SyncPlan::Keep {
                 remote: remote.to_owned(),
             }),
     );
     job
 }
 
+fn mime_type_of(local: &LocalFile) -> io::Result<Option<String>> {
+    match &local.content_type {
+        Some(content_type) => Ok(Some(content_type.clone())),
+        None => Ok(infer::get_from_path(&local.path)?.map(|t| t.mime_type().to_string())),
+    }
+}
+
+/// Whether `path` is `pattern` itself or lies below the directory `pattern`
+pub fn covers(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_start_matches('/');
+    let directory = pattern.trim_end_matches('/');
+    path == pattern
+        || path
+            .strip_prefix(directory)
+            .is_some_and(|rest| directory.is_empty() || rest.starts_with('/'))
+}
+
+/// Deleting up to this many files is never refused by --max-delete-percent, so that a small site
+/// can still remove a page or two
+const PERCENT_FLOOR: usize = 10;
+
+/// Fail if `job` deletes more than `max_delete` files, every one of the `remote_files` files in the
+/// storage zone path, or more than `max_percent` percent of them once it deletes more than
+/// `PERCENT_FLOOR`. The lockfile is not counted.
+pub fn check_delete_limits(
+    job: &[SyncPlan],
+    remote_files: usize,
+    lockfile: &str,
+    max_delete: usize,
+    max_percent: u8,
+) -> Result<(), Error> {
+    let deletes = job
+        .iter()
+        .filter(|plan| matches!(plan, SyncPlan::Delete { remote } if remote != lockfile))
+        .count();
+    if deletes > max_delete {
+        return Err(Error::SafetyLimit(format!(
+            "Refusing to delete {deletes} files, more than --max-delete {max_delete}. \
+             Pass --allow-mass-delete if this is intended"
+        )));
+    }
+    // Like syncing an empty build directory, however small the site is
+    if remote_files > 0 && deletes >= remote_files {
+        return Err(Error::SafetyLimit(format!(
+            "Refusing to delete all {deletes} files in the storage zone path. \
+             Pass --allow-mass-delete if this is intended"
+        )));
+    }
+    if deletes > PERCENT_FLOOR && deletes * 100 > usize::from(max_percent) * remote_files {
+        return Err(Error::SafetyLimit(format!(
+            "Refusing to delete {deletes} of {remote_files} files, more than --max-delete-percent {max_percent}. \
+             Pass --allow-mass-delete if this is intended"
+        )));
+    }
+    Ok(())
+}
+
+/// Find the remote directories that have no files left in them once `plan_sync` has run,
+/// ordered so that the deepest directories come first.
+///
+/// Directories that start with an ignored prefix, or that contain one, are never included.
+pub fn plan_empty_directories(
+    local: &FxHashMap<String, LocalFile>,
+    remote: &RemoteListing,
+    ignore: &[String],
+) -> Vec<String> {
+    if !remote.is_complete() {
+        return vec![];
+    }
+    let removed = must_remove(local, &remote.files, ignore);
+    let remaining: Vec<&str> = local
+        .keys()
+        .map(|path| path.as_str())
+        .chain(
+            remote
+                .files
+                .keys()
+                .map(|path| path.as_str())
+                .filter(|path| !removed.contains(path)),
+        )
+        .collect();
+    let mut empty: Vec<String> = remote
+        .directories
+        .iter()
+        .filter(|dir| {
+            !ignore
+                .iter()
+                .any(|prefix| dir.starts_with(prefix) || prefix.starts_with(dir.as_str()))
+        })
+        .filter(|dir| !remaining.iter().any(|path| path.starts_with(dir.as_str())))
+        .cloned()
+        .collect();
+    empty.sort_by(|a, b| {
+        b.matches('/')
+            .count()
+            .cmp(&a.matches('/').count())
+            .then_with(|| a.cmp(b))
+    });
+    empty
+}
+
+/// Whether the content type bunny.net reports differs from the one we would upload with,
+/// ignoring parameters like charset
+fn content_type_drifted(
+    local: &LocalFile,
+    remote_content_type: &Option<String>,
+) -> io::Result<bool> {
+    let essence = |content_type: &str| {
+        content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    };
+    match remote_content_type {
+        Some(remote) => Ok(mime_type_of(local)?
+            .map(|local| essence(&local) != essence(remote))
+            .unwrap_or(false)),
+        None => Ok(false),
+    }
+}
+
 pub fn plan_execution<'a, F>(plan: &'a SyncPlan, read: F) -> anyhow::Result<Execution<'a>>
 where
     F: Fn(&'a PathBuf) -> io::Result<Vec<u8>>,
 {
     match plan {
         SyncPlan::Put { local, remote } => {
-            let content = fs::read(local)?;
-            let mime_type = infer::get_from_path(local)?.map(|t| t.mime_type());
+            let content = fs::read(&local.path)?;
+            let mime_type = mime_type_of(local)?;
             Ok(Execution {
                 remote,
                 action: SyncAction::Put { content, mime_type },
+                reason: Reason::NewFile,
             })
         }
         SyncPlan::Replace {
             local,
             remote,
             remote_checksum,
+            remote_content_type,
+            ..
+        } if local.checksum.is_some()
+            && &local.checksum == remote_checksum
+            && !content_type_drifted(local, remote_content_type)? =>
+        {
+            Ok(Execution {
+                remote,
+                action: SyncAction::Ignore,
+                reason: Reason::Unchanged,
+            })
+        }
+        SyncPlan::Replace {
+            local,
+            remote,
+            remote_checksum,
+            remote_content_type,
+            ..
         } => {
-            let content = read(local)?;
-            let mime_type = infer::get_from_path(local)?.map(|t| t.mime_type());
+            let content = read(&local.path)?;
+            let mime_type = mime_type_of(local)?;
             let digest: [u8; 32] = Sha256::digest(&content).into();
-            if &Some(digest) != remote_checksum {
+            let reason = if remote_checksum.is_none() {
+                Reason::NoRemoteChecksum
+            } else if &Some(digest) != remote_checksum {
+                Reason::ChecksumMismatch
+            } else if content_type_drifted(local, remote_content_type)? {
+                Reason::ContentTypeDrift
+            } else {
+                Reason::Unchanged
+            };
+            if reason != Reason::Unchanged {
                 Ok(Execution {
                     remote,
                     action: SyncAction::Put { content, mime_type },
+                    reason,
                 })
             } else {
                 Ok(Execution {
                     remote,
                     action: SyncAction::Ignore,
+                    reason,
                 })
             }
         }
         SyncPlan::Delete { remote } => Ok(Execution {
             remote,
             action: SyncAction::Delete,
+            reason: Reason::MissingLocally,
+        }),
+        SyncPlan::Keep { remote } => Ok(Execution {
+            remote,
+            action: SyncAction::Ignore,
+            reason: Reason::ProtectedByIgnore,
         }),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Execution, SyncAction, SyncPlan, plan_execution, plan_sync};
-    use crate::api::FileMeta;
+    use super::{
+        Execution, PERCENT_FLOOR, Reason, SyncAction, SyncPlan, check_delete_limits,
+        plan_empty_directories, plan_execution, plan_sync,
+    };
+    use crate::api::{FileMeta, RemoteListing};
+    use crate::local_path::LocalFile;
     use fxhash::FxHashMap;
     use sha2::{Digest, Sha256};
+    use std::fs;
     use std::path::PathBuf;
 
     #[test]
@@ -154,11 +384,13 @@ mod tests {
         let local_content = "hallois";
         let local = PathBuf::new().join("README.md");
         let plan = SyncPlan::Replace {
-            local: local,
+            local: local.into(),
             remote: "remote".to_string(),
             remote_checksum: Some(remote_checksum),
+            remote_content_type: None,
+            remote_length: None,
         };
-        let Execution { remote: _, action } =
+        let Execution { action, .. } =
             plan_execution(&plan, |_| Ok(local_content.as_bytes().to_vec())).unwrap();
         assert_eq!(
             action,
@@ -176,58 +408,188 @@ mod tests {
         let local_content = "hei";
         let local = PathBuf::new().join("README.md");
         let plan = SyncPlan::Replace {
-            local: local,
+            local: local.into(),
             remote: "remote".to_string(),
             remote_checksum: Some(remote_checksum),
+            remote_content_type: None,
+            remote_length: None,
         };
-        let Execution { remote: _, action } =
+        let Execution { action, .. } =
             plan_execution(&plan, |_| Ok(local_content.as_bytes().to_vec())).unwrap();
         assert_eq!(action, SyncAction::Ignore);
     }
 
+    #[test]
+    fn trusts_precomputed_checksum() {
+        let remote_checksum: [u8; 32] = Sha256::digest("hei".as_bytes()).into();
+        let plan = SyncPlan::Replace {
+            local: LocalFile {
+                path: PathBuf::new().join("does-not-exist"),
+                checksum: Some(remote_checksum),
+                content_type: None,
+            },
+            remote: "remote".to_string(),
+            remote_checksum: Some(remote_checksum),
+            remote_content_type: None,
+            remote_length: None,
+        };
+        let Execution { action, .. } =
+            plan_execution(&plan, |_| panic!("Should not read the file")).unwrap();
+        assert_eq!(action, SyncAction::Ignore);
+    }
+
+    #[test]
+    fn replaces_on_content_type_drift() {
+        let checksum: [u8; 32] = Sha256::digest("hei".as_bytes()).into();
+        let plan = |remote_content_type: &str| SyncPlan::Replace {
+            local: LocalFile {
+                path: PathBuf::new().join("does-not-exist"),
+                checksum: Some(checksum),
+                content_type: Some("text/html".to_string()),
+            },
+            remote: "remote".to_string(),
+            remote_checksum: Some(checksum),
+            remote_content_type: Some(remote_content_type.to_string()),
+            remote_length: None,
+        };
+        let drifted = plan("application/octet-stream");
+        let Execution { action, reason, .. } =
+            plan_execution(&drifted, |_| Ok(b"hei".to_vec())).unwrap();
+        assert_eq!(reason, Reason::ContentTypeDrift);
+        assert!(matches!(action, SyncAction::Put { .. }));
+
+        let same = plan("text/html; charset=UTF-8");
+        let Execution { reason, .. } =
+            plan_execution(&same, |_| panic!("Should not read the file")).unwrap();
+        assert_eq!(reason, Reason::Unchanged);
+    }
+
+    #[test]
+    fn prefers_precomputed_content_type() {
+        let plan = SyncPlan::Replace {
+            local: LocalFile {
+                path: PathBuf::new().join("README.md"),
+                checksum: None,
+                content_type: Some("text/markdown".to_string()),
+            },
+            remote: "remote".to_string(),
+            remote_checksum: None,
+            remote_content_type: None,
+            remote_length: None,
+        };
+        let Execution { action, .. } = plan_execution(&plan, |_| Ok(b"content".to_vec())).unwrap();
+        assert_eq!(
+            action,
+            SyncAction::Put {
+                content: b"content".to_vec(),
+                mime_type: Some("text/markdown".to_string())
+            }
+        );
+    }
+
     #[test]
     fn deletes_everything_with_empty_local() {
         let local = FxHashMap::default();
         let mut remote = FxHashMap::default();
-        remote.insert("subfolder/index.html".into(), FileMeta { checksum: None });
-        let job = plan_sync(&local, &remote, &[]);
+        remote.insert(
+            "subfolder/index.html".into(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
+        let job = plan_sync(&local, &remote, &[], true);
         assert_eq!(
             job,
             vec![SyncPlan::Delete {
                 remote: "subfolder/index.html".to_string()
             }]
         );
+        assert!(check_delete_limits(&job, 1, ".thumper.lock", 1000, 50).is_err());
+    }
+
+    #[test]
+    fn refuses_mass_delete() {
+        let local = FxHashMap::default();
+        let mut remote = FxHashMap::default();
+        for name in ["a.html", "b.html", "c.html", ".thumper.lock"] {
+            remote.insert(
+                name.to_string(),
+                FileMeta {
+                    checksum: None,
+                    content_type: None,
+                    length: None,
+                },
+            );
+        }
+        let job = plan_sync(&local, &remote, &[], true);
+        let by_count = check_delete_limits(&job, 3, ".thumper.lock", 2, 100).unwrap_err();
+        assert!(by_count.to_string().contains("delete 3 files"));
+        let wipe = check_delete_limits(&job, 3, ".thumper.lock", 1000, 50).unwrap_err();
+        assert!(wipe.to_string().contains("delete all 3 files"));
+        // Too few deletes for the percentage to count
+        assert!(check_delete_limits(&job, 4, ".thumper.lock", 1000, 50).is_ok());
+        assert!(check_delete_limits(&job, 6, ".thumper.lock", 3, 50).is_ok());
+        assert!(check_delete_limits(&[], 0, ".thumper.lock", 0, 0).is_ok());
+
+        let job: Vec<_> = (0..=PERCENT_FLOOR)
+            .map(|n| SyncPlan::Delete {
+                remote: format!("{n}.html"),
+            })
+            .collect();
+        let by_percent = check_delete_limits(&job, 20, ".thumper.lock", 1000, 50).unwrap_err();
+        assert!(by_percent.to_string().contains("delete 11 of 20 files"));
+        assert!(check_delete_limits(&job, 22, ".thumper.lock", 1000, 50).is_ok());
     }
 
     #[test]
     fn skips_deleting_ignored_prefixes() {
         let local = FxHashMap::default();
         let mut remote = FxHashMap::default();
-        remote.insert("subfolder/index.html".into(), FileMeta { checksum: None });
+        remote.insert(
+            "subfolder/index.html".into(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
         remote.insert(
             "other_subfolder/index.html".into(),
-            FileMeta { checksum: None },
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
         );
-        let job = plan_sync(&local, &remote, &["other_subfolder".into()]);
+        let job = plan_sync(&local, &remote, &["other_subfolder".into()], true);
         assert_eq!(
             job,
-            vec![SyncPlan::Delete {
-                remote: "subfolder/index.html".to_string()
-            }]
+            vec![
+                SyncPlan::Delete {
+                    remote: "subfolder/index.html".to_string()
+                },
+                SyncPlan::Keep {
+                    remote: "other_subfolder/index.html".to_string()
+                }
+            ]
         );
+        let Execution { reason, .. } = plan_execution(&job[1], fs::read).unwrap();
+        assert_eq!(reason, Reason::ProtectedByIgnore);
     }
 
     #[test]
     fn syncs_missing_files() {
         let mut local = FxHashMap::default();
-        local.insert("subfolder/index.html".into(), PathBuf::new());
+        local.insert("subfolder/index.html".into(), PathBuf::new().into());
         let remote = FxHashMap::default();
-        let job = plan_sync(&local, &remote, &[]);
+        let job = plan_sync(&local, &remote, &[], true);
         assert_eq!(
             job,
             vec![SyncPlan::Put {
                 remote: "subfolder/index.html".to_string(),
-                local: PathBuf::new()
+                local: PathBuf::new().into()
             }]
         );
     }
@@ -235,16 +597,25 @@ mod tests {
     #[test]
     fn compares_files_in_both() {
         let mut local = FxHashMap::default();
-        local.insert("subfolder/index.html".into(), PathBuf::new());
+        local.insert("subfolder/index.html".into(), PathBuf::new().into());
         let mut remote = FxHashMap::default();
-        remote.insert("subfolder/index.html".into(), FileMeta { checksum: None });
-        let job = plan_sync(&local, &remote, &[]);
+        remote.insert(
+            "subfolder/index.html".into(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
+        let job = plan_sync(&local, &remote, &[], true);
         assert_eq!(
             job,
             vec![SyncPlan::Replace {
                 remote: "subfolder/index.html".to_string(),
-                local: PathBuf::new(),
-                remote_checksum: None
+                local: PathBuf::new().into(),
+                remote_checksum: None,
+                remote_content_type: None,
+                remote_length: None,
             }]
         );
     }
@@ -252,13 +623,13 @@ mod tests {
     #[test]
     fn sorts_html_files_last() {
         let mut local = FxHashMap::default();
-        local.insert("z.txt".into(), PathBuf::new());
-        local.insert("a.html".into(), PathBuf::new());
-        local.insert("b.htm".into(), PathBuf::new());
-        local.insert("c.jpg".into(), PathBuf::new());
+        local.insert("z.txt".into(), PathBuf::new().into());
+        local.insert("a.html".into(), PathBuf::new().into());
+        local.insert("b.htm".into(), PathBuf::new().into());
+        local.insert("c.jpg".into(), PathBuf::new().into());
 
         let remote = FxHashMap::default();
-        let job = plan_sync(&local, &remote, &[]);
+        let job = plan_sync(&local, &remote, &[], true);
 
         // HTML files should be at the end
         assert_eq!(job[0].remote(), "c.jpg");
@@ -273,9 +644,11 @@ mod tests {
         let local_content = "content";
         let local = PathBuf::new().join("README.md");
         let plan = SyncPlan::Replace {
-            local: local,
+            local: local.into(),
             remote: "remote".to_string(),
             remote_checksum: None,
+            remote_content_type: None,
+            remote_length: None,
         };
         let execution = plan_execution(&plan, |_| Ok(local_content.as_bytes().to_vec())).unwrap();
         assert_eq!(
@@ -290,17 +663,121 @@ mod tests {
     #[test]
     fn test_must_remove() {
         let mut local = FxHashMap::default();
-        local.insert("file1.txt".into(), PathBuf::new());
-        local.insert("file2.txt".into(), PathBuf::new());
+        local.insert("file1.txt".into(), PathBuf::new().into());
+        local.insert("file2.txt".into(), PathBuf::new().into());
 
         let mut remote = FxHashMap::default();
-        remote.insert("file1.txt".into(), FileMeta { checksum: None });
-        remote.insert("file3.txt".into(), FileMeta { checksum: None });
-        remote.insert("ignored/file4.txt".into(), FileMeta { checksum: None });
+        remote.insert(
+            "file1.txt".into(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
+        remote.insert(
+            "file3.txt".into(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
+        remote.insert(
+            "ignored/file4.txt".into(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
 
         let to_remove = super::must_remove(&local, &remote, &["ignored".to_string()]);
 
         assert_eq!(to_remove.len(), 1);
         assert!(to_remove.contains("file3.txt"));
     }
+
+    #[test]
+    fn finds_directories_emptied_by_sync() {
+        let mut local = FxHashMap::default();
+        local.insert("docs/index.html".into(), PathBuf::new().into());
+        let mut remote = RemoteListing::default();
+        for file in [
+            "docs/index.html",
+            "docs/old/a.html",
+            "docs/old/deeper/b.html",
+            "keep/c.html",
+        ] {
+            remote.files.insert(
+                file.into(),
+                FileMeta {
+                    checksum: None,
+                    content_type: None,
+                    length: None,
+                },
+            );
+        }
+        for dir in [
+            "docs/",
+            "docs/old/",
+            "docs/old/deeper/",
+            "docs/empty/",
+            "keep/",
+            "keep/empty/",
+        ] {
+            remote.directories.insert(dir.into());
+        }
+
+        let empty = plan_empty_directories(&local, &remote, &["keep/".to_string()]);
+        assert_eq!(empty, vec!["docs/old/deeper/", "docs/empty/", "docs/old/"]);
+    }
+
+    #[test]
+    fn plans_no_deletes_from_incomplete_listing() {
+        let mut local = FxHashMap::default();
+        local.insert("index.html".into(), PathBuf::new().into());
+        let mut remote = RemoteListing {
+            unlisted: vec!["css/".into()],
+            ..Default::default()
+        };
+        remote.files.insert(
+            "old.html".into(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
+        remote.directories.insert("old/".into());
+
+        let job = plan_sync(&local, &remote.files, &[], remote.is_complete());
+        assert_eq!(
+            job,
+            vec![SyncPlan::Put {
+                local: PathBuf::new().into(),
+                remote: "index.html".into(),
+            }]
+        );
+        assert!(plan_empty_directories(&local, &remote, &[]).is_empty());
+    }
+
+    #[test]
+    fn keeps_directories_containing_ignored_prefixes() {
+        let local = FxHashMap::default();
+        let mut remote = RemoteListing::default();
+        remote.files.insert(
+            "site/a.html".into(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
+        remote.directories.insert("site/".into());
+        remote.directories.insert("other/".into());
+
+        let empty = plan_empty_directories(&local, &remote, &["site/preview/".to_string()]);
+        assert_eq!(empty, vec!["other/"]);
+    }
 }
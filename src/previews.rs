@@ -0,0 +1,306 @@
+use crate::api::StorageZoneClient;
+use crate::cli::{PreviewsAction, PreviewsListArgs, PreviewsPruneArgs};
+use crate::files::{Change, client_for, print_changes};
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+/// File in the root of each preview with its metadata
+pub const PREVIEW_MARKER: &str = ".thumper-preview.json";
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct PreviewMarker {
+    name: String,
+    created: String,
+    updated: String,
+    git_ref: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Preview {
+    name: String,
+    path: String,
+    marker: Option<PreviewMarker>,
+}
+
+/// The directory of `template` that previews are created in. `{branch}` must be its last path
+/// component.
+fn previews_root(template: &str) -> anyhow::Result<&str> {
+    match template.trim_start_matches('/').split_once("{branch}") {
+        Some((root, "" | "/")) if root.is_empty() || root.ends_with('/') => Ok(root),
+        _ => Err(anyhow!(
+            "Invalid preview template {template}, it must end with /{{branch}}/"
+        )),
+    }
+}
+
+/// Add the directory of `template` that previews are created in to `ignore`, unless the sync to
+/// `path` deploys into it, so that syncing the rest of the storage zone never deletes previews
+pub fn reserve_previews(ignore: &mut Vec<String>, template: &str, path: &str) {
+    let path = path.trim_start_matches('/');
+    if let Ok(root) = previews_root(template)
+        && !root.is_empty()
+        && !path.starts_with(root)
+        && !ignore.iter().any(|prefix| prefix == root)
+    {
+        ignore.push(root.to_string());
+    }
+}
+
+/// Replace everything but letters, digits, `-`, `_` and `.` in a branch name, so that
+/// `feature/login` becomes `feature-login`
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// The path inside the storage zone that the preview `name` is deployed to
+pub fn preview_path(template: &str, name: &str) -> anyhow::Result<String> {
+    let name = sanitize(name);
+    if name.trim_matches('.').is_empty() {
+        return Err(anyhow!("Invalid preview name {name}"));
+    }
+    Ok(format!("{}{name}/", previews_root(template)?))
+}
+
+/// The commit checked out in the working directory, if it is a git repository
+pub fn current_git_ref() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Write the marker of the preview `name` at `path`, keeping the creation time of an earlier one
+pub fn write_marker(
+    client: &StorageZoneClient,
+    path: &str,
+    name: &str,
+    git_ref: Option<String>,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let marker_path = format!("{path}{PREVIEW_MARKER}");
+    let now = now.to_rfc3339();
+    let created = client
+        .read_file(marker_path.as_str())
+        .ok()
+        .and_then(|marker| serde_json::from_str::<PreviewMarker>(&marker).ok())
+        .map(|marker| marker.created)
+        .unwrap_or_else(|| now.clone());
+    let marker = PreviewMarker {
+        name: name.to_string(),
+        created,
+        updated: now,
+        git_ref,
+    };
+    client.put_file(
+        marker_path.as_str(),
+        serde_json::to_vec_pretty(&marker)?,
+        Some("application/json"),
+    )?;
+    Ok(())
+}
+
+fn previews(client: &StorageZoneClient, template: &str) -> anyhow::Result<Vec<Preview>> {
+    let root = previews_root(template)?;
+    let mut previews = vec![];
+    for directory in client.ls_dir(root)? {
+        if !directory.is_directory {
+            continue;
+        }
+        let path = format!("{root}{}/", directory.object_name);
+        let marker = client
+            .read_file(&format!("{path}{PREVIEW_MARKER}"))
+            .ok()
+            .and_then(|marker| serde_json::from_str(&marker).ok());
+        previews.push(Preview {
+            name: directory.object_name,
+            path,
+            marker,
+        });
+    }
+    previews.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(previews)
+}
+
+/// Whether `preview` was last deployed more than `age` before `now`. Previews without a marker
+/// are never stale, since there is no telling when they were deployed.
+fn is_stale(preview: &Preview, age: Duration, now: DateTime<Utc>) -> bool {
+    preview
+        .marker
+        .as_ref()
+        .and_then(|marker| DateTime::parse_from_rfc3339(&marker.updated).ok())
+        .is_some_and(|updated| now - updated.with_timezone(&Utc) > age)
+}
+
+fn prune(
+    client: &StorageZoneClient,
+    template: &str,
+    age: Duration,
+    keep: &[String],
+    now: DateTime<Utc>,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Change>> {
+    let keep: Vec<String> = keep.iter().map(|name| sanitize(name)).collect();
+    let mut changes = vec![];
+    for preview in previews(client, template)? {
+        if keep.contains(&preview.name) || !is_stale(&preview, age, now) {
+            continue;
+        }
+        if !dry_run {
+            client.delete_file(preview.path.as_str())?;
+        }
+        changes.push(Change {
+            path: preview.path,
+            action: "delete",
+            dry_run,
+        });
+    }
+    Ok(changes)
+}
+
+pub fn do_previews(command: PreviewsAction) -> anyhow::Result<()> {
+    match command {
+        PreviewsAction::List { args } => {
+            let PreviewsListArgs {
+                zone,
+                template,
+                json,
+            } = args;
+            let client = client_for(zone)?;
+            let previews = previews(&client, template.as_str())?;
+            if json {
+                println!("{}", serde_json::to_string(&previews)?);
+                return Ok(());
+            }
+            for Preview { name, marker, .. } in previews {
+                match marker {
+                    Some(PreviewMarker {
+                        created,
+                        updated,
+                        git_ref,
+                        ..
+                    }) => println!(
+                        "{name}\tcreated {created}\tupdated {updated}\t{}",
+                        git_ref.unwrap_or_default()
+                    ),
+                    None => println!("{name}\tno {PREVIEW_MARKER}"),
+                }
+            }
+            Ok(())
+        }
+        PreviewsAction::Prune { args } => {
+            let PreviewsPruneArgs {
+                zone,
+                template,
+                older_than,
+                keep,
+                dry_run,
+                json,
+            } = args;
+            let client = client_for(zone)?;
+            let changes = prune(
+                &client,
+                template.as_str(),
+                older_than,
+                &keep,
+                Utc::now(),
+                dry_run,
+            )?;
+            print_changes(&changes, json)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeStorageZone;
+
+    fn time(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn templates_preview_paths() {
+        assert_eq!(
+            preview_path("previews/{branch}/", "feature/login").unwrap(),
+            "previews/feature-login/"
+        );
+        assert_eq!(preview_path("/site/{branch}", "42").unwrap(), "site/42/");
+        assert!(preview_path("site/pr-{branch}/", "42").is_err());
+        assert!(preview_path("previews/{branch}/site/", "main").is_err());
+        assert!(preview_path("previews/", "main").is_err());
+        assert!(preview_path("previews/{branch}/", "..").is_err());
+    }
+
+    #[test]
+    fn reserves_previews_unless_deploying_into_them() {
+        let reserved = |template, path| {
+            let mut ignore = vec![];
+            reserve_previews(&mut ignore, template, path);
+            ignore
+        };
+        assert_eq!(reserved("previews/{branch}/", "/"), ["previews/"]);
+        assert_eq!(reserved("/site/pr/{branch}", "site/"), ["site/pr/"]);
+        assert!(reserved("previews/{branch}/", "/previews/main/").is_empty());
+        assert!(reserved("{branch}/", "/").is_empty());
+        assert!(reserved("previews/pr-{branch}/", "/").is_empty());
+    }
+
+    #[test]
+    fn lists_and_prunes_stale_previews() {
+        let zone = FakeStorageZone::start("previews-zone");
+        zone.insert("previews/old/index.html", b"old");
+        zone.insert("previews/kept/index.html", b"kept");
+        zone.insert("previews/fresh/index.html", b"fresh");
+        zone.insert("previews/unknown/index.html", b"unknown");
+        let client = zone.client();
+        let template = "previews/{branch}/";
+        let long_ago = time("2025-04-01T00:00:00Z");
+        for name in ["old", "kept"] {
+            let path = preview_path(template, name).unwrap();
+            write_marker(&client, &path, name, Some("abc".into()), long_ago).unwrap();
+        }
+        write_marker(&client, "previews/fresh/", "fresh", None, long_ago).unwrap();
+        let now = time("2025-04-15T00:00:00Z");
+        write_marker(&client, "previews/fresh/", "fresh", None, now).unwrap();
+
+        let listed = previews(&client, template).unwrap();
+        let names: Vec<_> = listed.iter().map(|preview| preview.name.as_str()).collect();
+        assert_eq!(names, vec!["fresh", "kept", "old", "unknown"]);
+        let fresh = listed[0].marker.as_ref().unwrap();
+        assert_eq!(fresh.created, long_ago.to_rfc3339());
+        assert_eq!(fresh.updated, now.to_rfc3339());
+        assert!(listed[3].marker.is_none());
+
+        let pruned = prune(
+            &client,
+            template,
+            Duration::days(7),
+            &["kept".into()],
+            now,
+            false,
+        )
+        .unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].path, "previews/old/");
+        assert!(zone.get("previews/old/index.html").is_none());
+        assert!(zone.get("previews/kept/index.html").is_some());
+        assert!(zone.get("previews/unknown/index.html").is_some());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::events::{Event, Observer};
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often the progress line is redrawn on a terminal
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// How often a progress line is printed when stderr is not a terminal, like in CI logs
+const LINE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shows how far listing and syncing got on stderr. Redraws a single line on a terminal, and
+/// prints a plain line every now and then otherwise.
+pub struct ProgressDisplay {
+    tty: bool,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    directories: usize,
+    listed_files: usize,
+    /// Set when the plan is ready and syncing starts
+    started: Option<Instant>,
+    total_jobs: usize,
+    /// Size of the local files to read and hash
+    total_bytes: u64,
+    jobs: usize,
+    /// Size of the local files read and hashed so far, and the time it took summed over workers
+    hashed: u64,
+    hash_ms: u64,
+    /// Bytes uploaded so far, unchanged files are only hashed
+    uploaded: u64,
+    drawn: Option<Instant>,
+}
+
+impl ProgressDisplay {
+    pub fn new() -> Self {
+        ProgressDisplay {
+            tty: io::stderr().is_terminal(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn interval(&self) -> Duration {
+        if self.tty {
+            REDRAW_INTERVAL
+        } else {
+            LINE_INTERVAL
+        }
+    }
+
+    fn print(&self, line: &str, last: bool) {
+        let mut stderr = io::stderr().lock();
+        let _ = match (self.tty, last) {
+            (true, false) => write!(stderr, "\r\x1b[2K{line}"),
+            (true, true) => writeln!(stderr, "\r\x1b[2K{line}"),
+            (false, _) => writeln!(stderr, "{line}"),
+        };
+        let _ = stderr.flush();
+    }
+}
+
+impl Observer for ProgressDisplay {
+    fn observe(&self, event: &Event) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        match event {
+            Event::Listed { files, .. } => {
+                state.directories += 1;
+                state.listed_files += files;
+            }
+            Event::PlanReady {
+                new_files,
+                existing_files,
+                deletes,
+                kept,
+                empty_directories,
+                bytes,
+            } => {
+                state.total_jobs = new_files + existing_files + deletes + kept + empty_directories;
+                state.total_bytes = *bytes;
+                state.started = Some(now);
+                // Finish the listing line so that the sync starts on a line of its own
+                if self.tty && state.drawn.is_some() {
+                    let _ = writeln!(io::stderr());
+                }
+                state.drawn = None;
+                return;
+            }
+            Event::Job {
+                bytes,
+                size,
+                hash_ms,
+                ..
+            } => {
+                state.jobs += 1;
+                state.hashed += size;
+                state.hash_ms += hash_ms;
+                state.uploaded += bytes;
+            }
+            Event::JobFailed { .. } => state.jobs += 1,
+            Event::Summary { .. } => {
+                if state.started.is_some() {
+                    self.print(state.line(now).as_str(), true);
+                }
+                return;
+            }
+            _ => return,
+        }
+        if state
+            .drawn
+            .is_none_or(|drawn| now - drawn >= self.interval())
+        {
+            state.drawn = Some(now);
+            self.print(state.line(now).as_str(), false);
+        }
+    }
+}
+
+impl State {
+    fn line(&self, now: Instant) -> String {
+        let Some(started) = self.started else {
+            return format!(
+                "Listing: {} directories, {} files",
+                self.directories, self.listed_files
+            );
+        };
+        let elapsed = now - started;
+        let throughput = self.uploaded as f64 / elapsed.as_secs_f64().max(0.001);
+        let mut line = format!(
+            "Syncing: {}/{} files, hashed {} of {} in {}, uploaded {} at {}/s",
+            self.jobs,
+            self.total_jobs,
+            format_bytes(self.hashed),
+            format_bytes(self.total_bytes),
+            format_duration(Duration::from_millis(self.hash_ms)),
+            format_bytes(self.uploaded),
+            format_bytes(throughput as u64)
+        );
+        if let Some(eta) = self.eta(elapsed) {
+            line.push_str(format!(", ETA {}", format_duration(eta)).as_str());
+        } else if self.jobs == self.total_jobs {
+            line.push_str(format!(", done in {}", format_duration(elapsed)).as_str());
+        }
+        line
+    }
+
+    /// Time left, estimated from the share of files done. Which files need uploading is only
+    /// known once they are hashed, so there is no total of bytes to upload to go by.
+    fn eta(&self, elapsed: Duration) -> Option<Duration> {
+        if self.total_jobs == 0 {
+            return None;
+        }
+        let done = self.jobs as f64 / self.total_jobs as f64;
+        (done > 0.0 && self.jobs < self.total_jobs)
+            .then(|| elapsed.mul_f64((1.0 - done).max(0.0) / done))
+    }
+}
+
+/// `bytes` in B, KiB, MiB or GiB
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_listing_and_sync_progress() {
+        let now = Instant::now();
+        let mut state = State {
+            directories: 3,
+            listed_files: 40,
+            ..State::default()
+        };
+        assert_eq!(state.line(now), "Listing: 3 directories, 40 files");
+
+        state.started = Some(now);
+        state.total_jobs = 10;
+        state.total_bytes = 4 * 1024 * 1024;
+        state.jobs = 4;
+        state.hashed = 2 * 1024 * 1024;
+        state.hash_ms = 1500;
+        state.uploaded = 1024 * 1024;
+        assert_eq!(
+            state.line(now + Duration::from_secs(2)),
+            "Syncing: 4/10 files, hashed 2.0 MiB of 4.0 MiB in 1s, uploaded 1.0 MiB at 512.0 KiB/s, ETA 3s"
+        );
+
+        state.jobs = 10;
+        state.hashed = state.total_bytes;
+        state.hash_ms = 61_000;
+        assert_eq!(
+            state.line(now + Duration::from_secs(75)),
+            "Syncing: 10/10 files, hashed 4.0 MiB of 4.0 MiB in 1m 01s, uploaded 1.0 MiB at 13.7 KiB/s, done in 1m 15s"
+        );
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024 * 1024), "3072.0 GiB");
+    }
+}
@@ -0,0 +1,298 @@
+use crate::api::{FileMeta, RemoteListing, StorageZoneClient};
+use crate::cli::PullArgs;
+use crate::error::Error;
+use crate::local_path::{self, LocalFile};
+use crate::planning::{SyncPlan, plan_sync};
+use crate::{SyncJob, init_sync, with_reserved_prefixes};
+use anyhow::anyhow;
+use crossbeam::channel::unbounded;
+use fxhash::FxHashMap;
+use sha2::{Digest, Sha256};
+use std::path::{Component, Path, PathBuf};
+use std::{fs, thread};
+
+/// Plan a pull by running `plan_sync` with the storage zone as the source and the local
+/// directory as the destination.
+///
+/// In the resulting plan, `local` is where the file goes on disk along with the checksum
+/// bunny.net reported for it, `remote` is the path in the storage zone, and `remote_checksum`
+/// is the checksum of the file that is already on disk. Local files are only deleted when the
+/// listing of the storage zone is complete.
+fn plan_pull(
+    remote: &RemoteListing,
+    local: &FxHashMap<String, LocalFile>,
+    local_root: &Path,
+    path: &str,
+    ignore: &[String],
+) -> anyhow::Result<Vec<SyncPlan>> {
+    let source = remote
+        .files
+        .iter()
+        .map(|(name, meta)| {
+            let file = LocalFile {
+                path: local_target(local_root, path, name)?,
+                checksum: meta.checksum,
+                content_type: None,
+            };
+            Ok((name.clone(), file))
+        })
+        .collect::<Result<FxHashMap<_, _>, Error>>()?;
+    let mut destination = FxHashMap::default();
+    for (name, file) in local {
+        let content = fs::read(&file.path)?;
+        let checksum: [u8; 32] = Sha256::digest(&content).into();
+        destination.insert(
+            name.clone(),
+            FileMeta {
+                checksum: Some(checksum),
+                content_type: None,
+                length: Some(content.len() as u64),
+            },
+        );
+    }
+    Ok(plan_sync(
+        &source,
+        &destination,
+        ignore,
+        remote.is_complete(),
+    ))
+}
+
+/// Where the file `remote` below the storage zone path `path` goes in `local_root`. Refuses names
+/// that would end up outside of it, like `../.bashrc`, which bunny.net should never list.
+fn local_target(local_root: &Path, path: &str, remote: &str) -> Result<PathBuf, Error> {
+    let relative = remote
+        .strip_prefix(path.trim_start_matches('/'))
+        .unwrap_or(remote);
+    let relative = Path::new(relative);
+    if relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        Ok(local_root.join(relative))
+    } else {
+        Err(Error::SafetyLimit(format!(
+            "Refusing to pull {remote} to outside of {}",
+            local_root.display()
+        )))
+    }
+}
+
+fn download(client: &StorageZoneClient, remote: &str, local: &LocalFile) -> anyhow::Result<()> {
+    let content = client.download_file(remote)?;
+    if let Some(expected) = local.checksum {
+        let digest: [u8; 32] = Sha256::digest(&content).into();
+        if digest != expected {
+            return Err(anyhow!("Checksum mismatch after downloading {remote}"));
+        }
+    }
+    if let Some(parent) = local.path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(fs::write(&local.path, content)?)
+}
+
+fn execute_pull_job(
+    client: &StorageZoneClient,
+    job: SyncPlan,
+    local_root: &Path,
+    path: &str,
+    dry_run: bool,
+) -> anyhow::Result<(String, &'static str)> {
+    let event = match &job {
+        SyncPlan::Put { .. } => "download",
+        SyncPlan::Replace {
+            local,
+            remote_checksum,
+            ..
+        } if local.checksum.is_some() && &local.checksum == remote_checksum => "unchanged",
+        SyncPlan::Replace { .. } => "download",
+        SyncPlan::Delete { .. } => "delete",
+        SyncPlan::Keep { .. } => "keep",
+    };
+    match job {
+        SyncPlan::Put { local, remote } | SyncPlan::Replace { local, remote, .. } => {
+            if !dry_run && event == "download" {
+                download(client, remote.as_str(), &local)?;
+            }
+            Ok((remote, event))
+        }
+        SyncPlan::Keep { remote } => Ok((remote, event)),
+        SyncPlan::Delete { remote } => {
+            if !dry_run {
+                fs::remove_file(local_target(local_root, path, remote.as_str())?)?;
+            }
+            Ok((remote, event))
+        }
+    }
+}
+
+pub fn do_pull(args: PullArgs) -> anyhow::Result<()> {
+    let PullArgs {
+        endpoint,
+        access_key,
+        storage_zone,
+        local_path,
+        path,
+        dry_run,
+        delete,
+        ignore,
+        verbose,
+        concurrency,
+    } = args;
+    let ignore = with_reserved_prefixes(ignore);
+    let concurrency = concurrency.unwrap_or_else(num_cpus::get);
+
+    let SyncJob {
+        client,
+        path,
+        local_path,
+    } = init_sync(access_key, local_path, path, storage_zone, endpoint)?;
+    let local_root = PathBuf::from(local_path.as_str());
+    let local = if local_root.exists() {
+        local_path::files_by_remote_name(local_path.as_str(), path.as_str())?
+    } else {
+        FxHashMap::default()
+    };
+    let remote = client.list_files(path.as_str(), &ignore, concurrency)?;
+    let job: Vec<_> = plan_pull(&remote, &local, &local_root, path.as_str(), &ignore)?
+        .into_iter()
+        .filter(|job| delete || !matches!(job, SyncPlan::Delete { .. }))
+        .collect();
+
+    let (send_work, receive_work) = unbounded();
+    let (send_result, receive_result) = unbounded();
+    let expected = job.len();
+    let client = &client;
+    let local_root = local_root.as_path();
+    let path = path.as_str();
+
+    thread::scope(move |scope| {
+        for action in job {
+            send_work.send(action)?;
+        }
+        drop(send_work);
+
+        for _ in 0..concurrency {
+            let receive_work = receive_work.clone();
+            let send_result = send_result.clone();
+
+            scope.spawn(move || {
+                while let Ok(action) = receive_work.recv() {
+                    let r = execute_pull_job(client, action, local_root, path, dry_run);
+                    send_result.send(r)?;
+                }
+                Ok::<(), anyhow::Error>(())
+            });
+        }
+
+        for _ in 0..expected {
+            let (remote, event) = receive_result.recv()??;
+            if verbose || dry_run {
+                println!("{remote}: {event}");
+            }
+        }
+
+        Ok::<_, anyhow::Error>(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeStorageZone, TempDir};
+
+    #[test]
+    fn plans_downloads_for_new_and_changed_files() {
+        let dir = TempDir::new("plan-pull");
+        fs::write(dir.join("same.txt"), "same").unwrap();
+        fs::write(dir.join("changed.txt"), "old").unwrap();
+        fs::write(dir.join("extra.txt"), "extra").unwrap();
+
+        let mut remote = RemoteListing::default();
+        for (name, content) in [
+            ("docs/same.txt", "same"),
+            ("docs/changed.txt", "new"),
+            ("docs/new.txt", "new"),
+        ] {
+            let checksum: [u8; 32] = Sha256::digest(content).into();
+            remote.files.insert(
+                name.to_string(),
+                FileMeta {
+                    checksum: Some(checksum),
+                    content_type: None,
+                    length: None,
+                },
+            );
+        }
+        let local = local_path::files_by_remote_name(dir.to_str().unwrap(), "docs/").unwrap();
+        let client = FakeStorageZone::start("plan-pull").client();
+        let mut events: Vec<_> = plan_pull(&remote, &local, &dir, "docs/", &[])
+            .unwrap()
+            .into_iter()
+            .map(|job| execute_pull_job(&client, job, &dir, "docs/", true))
+            .map(Result::unwrap)
+            .collect();
+        events.sort();
+
+        assert_eq!(
+            events,
+            vec![
+                ("docs/changed.txt".to_string(), "download"),
+                ("docs/extra.txt".to_string(), "delete"),
+                ("docs/new.txt".to_string(), "download"),
+                ("docs/same.txt".to_string(), "unchanged"),
+            ]
+        );
+    }
+
+    #[test]
+    fn pulls_subtree_into_local_directory() {
+        let zone = FakeStorageZone::start("pull-zone");
+        zone.insert("docs/index.html", b"<html></html>");
+        zone.insert("docs/css/site.css", b"body {}");
+        zone.insert("other/secret.txt", b"nope");
+        let client = zone.client();
+        let dir = TempDir::new("pull");
+        fs::write(dir.join("stale.txt"), "stale").unwrap();
+
+        let remote = client.list_files("docs/", &[], 2).unwrap();
+        let local = local_path::files_by_remote_name(dir.to_str().unwrap(), "docs/").unwrap();
+        for job in plan_pull(&remote, &local, &dir, "docs/", &[]).unwrap() {
+            execute_pull_job(&client, job, &dir, "docs/", false).unwrap();
+        }
+
+        assert_eq!(fs::read(dir.join("index.html")).unwrap(), b"<html></html>");
+        assert_eq!(fs::read(dir.join("css/site.css")).unwrap(), b"body {}");
+        assert!(!dir.join("stale.txt").exists());
+        assert!(!dir.join("secret.txt").exists());
+    }
+
+    #[test]
+    fn refuses_to_pull_outside_of_the_local_directory() {
+        let root = Path::new("site");
+        assert_eq!(
+            local_target(root, "docs/", "docs/css/site.css").unwrap(),
+            root.join("css/site.css")
+        );
+        for name in ["docs/../../.bashrc", "docs//etc/passwd", "../secret.txt"] {
+            let err = local_target(root, "docs/", name).unwrap_err();
+            assert!(matches!(err, Error::SafetyLimit(_)), "{name}");
+        }
+
+        let mut remote = RemoteListing::default();
+        remote.files.insert(
+            "docs/../escape.txt".into(),
+            FileMeta {
+                checksum: None,
+                content_type: None,
+                length: None,
+            },
+        );
+        let err = plan_pull(&remote, &FxHashMap::default(), root, "docs/", &[]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Refusing to pull docs/../escape.txt")
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use crate::api::{PullZoneClient, StorageZoneClient};
+use crate::cli::{PullZoneArgs, ReleaseArgs, RollbackArgs};
+use crate::events::{Events, Output};
+use crate::files::{Change, client_for, print_changes};
+use crate::local_path::{self, LocalFile};
+use crate::planning::plan_sync;
+use crate::{ExecuteOptions, execute_sync, normalize_path, take_lock, trash, unlock, use_api_key};
+use anyhow::anyhow;
+use fxhash::FxHashMap;
+
+/// The releases of a site in a storage zone, and the pull zone serving one of them
+struct Releases<'a> {
+    storage: &'a StorageZoneClient,
+    pull_zone: &'a PullZoneClient,
+    /// Origin URL of the storage zone, without the releases path
+    origin: &'a str,
+    /// Directory of the releases inside the storage zone, with a trailing `/`
+    path: &'a str,
+}
+
+impl Releases<'_> {
+    fn origin_url_of(&self, release: &str) -> String {
+        format!(
+            "{}/{}{release}",
+            self.origin.trim_end_matches('/'),
+            self.path.trim_start_matches('/')
+        )
+    }
+
+    /// Release ids in the storage zone, oldest first
+    fn list(&self) -> anyhow::Result<Vec<String>> {
+        let mut releases: Vec<String> = self
+            .storage
+            .ls_dir(self.path)?
+            .into_iter()
+            .filter(|file| file.is_directory)
+            .map(|file| file.object_name)
+            .collect();
+        releases.sort();
+        Ok(releases)
+    }
+
+    /// The release the pull zone serves, if it serves one
+    fn current(&self) -> anyhow::Result<Option<String>> {
+        let origin_url = self.pull_zone.origin_url()?;
+        Ok(origin_url
+            .strip_prefix(self.origin_url_of("").as_str())
+            .map(|release| release.trim_end_matches('/').to_string())
+            .filter(|release| !release.is_empty() && !release.contains('/')))
+    }
+
+    /// Point the pull zone to `release` and purge its cache, which still holds the files of the
+    /// release it served before
+    fn switch(&self, release: &str) -> anyhow::Result<()> {
+        self.pull_zone
+            .set_origin_url(self.origin_url_of(release).as_str())?;
+        self.pull_zone.purge_cache()?;
+        Ok(())
+    }
+
+    /// Upload `local` as the release `release` and switch the pull zone to it, returning the
+    /// release that was served before
+    fn publish(
+        &self,
+        release: &str,
+        local: &FxHashMap<String, LocalFile>,
+        options: ExecuteOptions,
+        lockfile: &str,
+        concurrency: usize,
+    ) -> anyhow::Result<Option<String>> {
+        let previous = self.current()?;
+        // Files of an earlier attempt at the same release are only uploaded again if they differ
+        let remote = self.storage.list_files(
+            format!("{}{release}/", self.path).as_str(),
+            &[],
+            concurrency,
+        )?;
+        let job = plan_sync(local, &remote.files, &[], remote.is_complete());
+        execute_sync(options, job, self.storage, lockfile, concurrency)?;
+        if !options.dry_run {
+            self.switch(release)?;
+        }
+        Ok(previous)
+    }
+
+    /// Delete the oldest releases so that `keep` remain, never deleting those in `protect`
+    fn prune(&self, keep: usize, protect: &[&str], dry_run: bool) -> anyhow::Result<Vec<Change>> {
+        let releases = self.list()?;
+        let excess = releases.len().saturating_sub(keep);
+        let mut changes = vec![];
+        for release in releases
+            .iter()
+            .filter(|release| !protect.contains(&release.as_str()))
+            .take(excess)
+        {
+            let directory = format!("{}{release}/", self.path);
+            if !dry_run {
+                self.storage.delete_file(directory.as_str())?;
+            }
+            changes.push(Change {
+                path: directory,
+                action: "delete",
+                dry_run,
+            });
+        }
+        Ok(changes)
+    }
+
+    /// The release to roll back to from `current`, the newest one before it unless `to` is given
+    fn rollback_target(&self, current: Option<&str>, to: Option<&str>) -> anyhow::Result<String> {
+        let releases = self.list()?;
+        match to {
+            Some(to) if releases.iter().any(|release| release == to) => Ok(to.to_string()),
+            Some(to) => Err(anyhow!("There is no release {to} in {}", self.path)),
+            None => {
+                let current =
+                    current.ok_or_else(|| anyhow!("The pull zone does not serve a release"))?;
+                releases
+                    .into_iter()
+                    .rev()
+                    .find(|release| release.as_str() < current)
+                    .ok_or_else(|| anyhow!("There is no release before {current}"))
+            }
+        }
+    }
+}
+
+fn pull_zone_client(args: &PullZoneArgs) -> anyhow::Result<PullZoneClient> {
+    let key = use_api_key(args.api_key.clone())?;
+    Ok(PullZoneClient::new(
+        key,
+        args.api_endpoint.clone(),
+        args.pull_zone,
+    ))
+}
+
+pub fn do_release(args: ReleaseArgs) -> anyhow::Result<()> {
+    let ReleaseArgs {
+        zone,
+        pull_zone,
+        local_path,
+        keep,
+        lockfile,
+        force,
+        dry_run,
+        verbose,
+        concurrency,
+    } = args;
+    let concurrency = concurrency.unwrap_or_else(num_cpus::get);
+    let keep = usize::try_from(keep)?;
+    let storage = client_for(zone)?.with_events(Events::printing(Output::Text, verbose || dry_run));
+    let api = pull_zone_client(&pull_zone)?;
+    let path = normalize_path(pull_zone.releases_path.trim_start_matches('/').to_string());
+    let releases = Releases {
+        storage: &storage,
+        pull_zone: &api,
+        origin: pull_zone.origin.as_str(),
+        path: path.as_str(),
+    };
+
+    let release = trash::deploy_id();
+    let release_path = format!("{path}{release}/");
+    let local = local_path::files_by_remote_name(
+        normalize_path(local_path).as_str(),
+        release_path.as_str(),
+    )?;
+    let lockfile = format!("{path}{lockfile}");
+    if !dry_run {
+        take_lock(&storage, lockfile.as_str(), force, None)?;
+    }
+    let options = ExecuteOptions {
+        dry_run,
+        ..Default::default()
+    };
+    let published = releases.publish(
+        release.as_str(),
+        &local,
+        options,
+        lockfile.as_str(),
+        concurrency,
+    );
+    let result = published.and_then(|previous| {
+        let switched = if dry_run { "Would switch" } else { "Switched" };
+        match &previous {
+            Some(previous) => eprintln!("{switched} from release {previous} to {release}"),
+            None => eprintln!("{switched} to release {release}"),
+        }
+        let mut protect = vec![release.as_str()];
+        protect.extend(previous.as_deref());
+        let pruned = releases.prune(keep, &protect, dry_run)?;
+        print_changes(&pruned, false)
+    });
+    if dry_run {
+        return result;
+    }
+    unlock(&storage, lockfile.as_str(), result)
+}
+
+pub fn do_rollback(args: RollbackArgs) -> anyhow::Result<()> {
+    let RollbackArgs {
+        zone,
+        pull_zone,
+        to,
+        dry_run,
+    } = args;
+    let storage = client_for(zone)?;
+    let api = pull_zone_client(&pull_zone)?;
+    let path = normalize_path(pull_zone.releases_path.trim_start_matches('/').to_string());
+    let releases = Releases {
+        storage: &storage,
+        pull_zone: &api,
+        origin: pull_zone.origin.as_str(),
+        path: path.as_str(),
+    };
+    let current = releases.current()?;
+    let target = releases.rollback_target(current.as_deref(), to.as_deref())?;
+    if !dry_run {
+        releases.switch(target.as_str())?;
+    }
+    println!(
+        "{} from release {} to {target}",
+        if dry_run { "Would switch" } else { "Switched" },
+        current.as_deref().unwrap_or("none")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakePullZoneApi, FakeStorageZone, TempDir};
+    use std::fs;
+
+    const ORIGIN: &str = "https://site.b-cdn.net";
+
+    #[test]
+    fn publishes_switches_and_prunes_releases() {
+        let zone = FakeStorageZone::start("release-zone");
+        zone.insert("releases/20250101T000000Z/index.html", b"one");
+        zone.insert("releases/20250102T000000Z/index.html", b"two");
+        zone.insert("releases/20250103T000000Z/index.html", b"three");
+        let storage = zone.client();
+        let api = FakePullZoneApi::start();
+        api.set_origin_url(7, "https://site.b-cdn.net/releases/20250103T000000Z");
+        let pull_zone = api.client(7);
+        let releases = Releases {
+            storage: &storage,
+            pull_zone: &pull_zone,
+            origin: ORIGIN,
+            path: "releases/",
+        };
+
+        // An earlier attempt at the release uploaded index.html already
+        zone.insert("releases/20250104T000000Z/index.html", b"four");
+        zone.fail(
+            "releases/20250104T000000Z/index.html",
+            "500 Internal Server Error",
+        );
+        zone.insert("releases/20250104T000000Z/stale.html", b"stale");
+
+        let dir = TempDir::new("release");
+        fs::write(dir.join("index.html"), "four").unwrap();
+        let mut local = FxHashMap::default();
+        local.insert(
+            "releases/20250104T000000Z/index.html".to_string(),
+            LocalFile::from(dir.join("index.html")),
+        );
+
+        let previous = releases
+            .publish(
+                "20250104T000000Z",
+                &local,
+                ExecuteOptions::default(),
+                "releases/.thumper.lock",
+                2,
+            )
+            .unwrap();
+        assert_eq!(previous.as_deref(), Some("20250103T000000Z"));
+        assert_eq!(
+            api.origin_url(7).as_deref(),
+            Some("https://site.b-cdn.net/releases/20250104T000000Z")
+        );
+        assert_eq!(api.purges(7), 1);
+        assert_eq!(
+            zone.get("releases/20250104T000000Z/index.html"),
+            Some(b"four".to_vec())
+        );
+        assert_eq!(zone.get("releases/20250104T000000Z/stale.html"), None);
+
+        let pruned = releases
+            .prune(2, &["20250104T000000Z", "20250103T000000Z"], false)
+            .unwrap();
+        assert_eq!(pruned.len(), 2);
+        assert_eq!(
+            releases.list().unwrap(),
+            vec!["20250103T000000Z", "20250104T000000Z"]
+        );
+    }
+
+    #[test]
+    fn rolls_back_to_previous_release() {
+        let zone = FakeStorageZone::start("rollback-zone");
+        zone.insert("releases/20250101T000000Z/index.html", b"one");
+        zone.insert("releases/20250102T000000Z/index.html", b"two");
+        let storage = zone.client();
+        let api = FakePullZoneApi::start();
+        api.set_origin_url(7, "https://site.b-cdn.net/releases/20250102T000000Z/");
+        let pull_zone = api.client(7);
+        let releases = Releases {
+            storage: &storage,
+            pull_zone: &pull_zone,
+            origin: "https://site.b-cdn.net/",
+            path: "releases/",
+        };
+
+        let current = releases.current().unwrap();
+        assert_eq!(current.as_deref(), Some("20250102T000000Z"));
+        let target = releases.rollback_target(current.as_deref(), None).unwrap();
+        assert_eq!(target, "20250101T000000Z");
+        assert!(releases.rollback_target(Some(&target), None).is_err());
+        assert!(releases.rollback_target(None, Some("missing")).is_err());
+
+        releases.switch(target.as_str()).unwrap();
+        assert_eq!(
+            api.origin_url(7).as_deref(),
+            Some("https://site.b-cdn.net/releases/20250101T000000Z")
+        );
+        assert_eq!(api.purges(7), 1);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::check::{Change, pending_changes};
+use crate::planning::{Reason, SyncPlan, covers};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "Commands:
+  y          run the selected changes
+  a          approve all changes and run them
+  r          reject all changes
+  d PATH     deselect a path, or every path below a directory
+  s PATH     select a path, or every path below a directory
+  l          show the plan again";
+
+struct Selection {
+    changes: Vec<(String, Change, Reason)>,
+    selected: Vec<bool>,
+}
+
+fn directory_of(path: &str) -> &str {
+    match path.trim_end_matches('/').rfind('/') {
+        Some(index) => &path[..=index],
+        None => "/",
+    }
+}
+
+impl Selection {
+    fn set(&mut self, pattern: &str, selected: bool) -> usize {
+        let mut count = 0;
+        for (index, (path, _, _)) in self.changes.iter().enumerate() {
+            if covers(pattern, path) {
+                self.selected[index] = selected;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn show(&self, output: &mut impl Write) -> std::io::Result<()> {
+        for (kind, title) in [
+            (Change::New, "New files"),
+            (Change::Changed, "Changed files"),
+            (Change::Delete, "Deletions"),
+        ] {
+            let mut by_directory: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+            for (index, (path, change, _)) in self.changes.iter().enumerate() {
+                if *change == kind {
+                    by_directory
+                        .entry(directory_of(path))
+                        .or_default()
+                        .push(index);
+                }
+            }
+            if by_directory.is_empty() {
+                continue;
+            }
+            writeln!(output, "{title}:")?;
+            for (directory, indices) in by_directory {
+                writeln!(output, "  {directory}")?;
+                for index in indices {
+                    let (path, change, reason) = &self.changes[index];
+                    let check = if self.selected[index] { 'x' } else { ' ' };
+                    writeln!(
+                        output,
+                        "    [{check}] {} {path} ({reason})",
+                        change.marker()
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rejected(&self) -> Vec<&str> {
+        self.changes
+            .iter()
+            .zip(&self.selected)
+            .filter(|(_, selected)| !**selected)
+            .map(|((path, _, _), _)| path.as_str())
+            .collect()
+    }
+}
+
+/// Show the pending changes of a sync and let the user approve them, reject them, or deselect
+/// single paths and directories. Returns the approved part of `job` and `empty_directories`.
+///
+/// Reaching the end of `input` rejects everything.
+pub fn review_plan(
+    mut job: Vec<SyncPlan>,
+    mut empty_directories: Vec<String>,
+    lockfile: &str,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> anyhow::Result<(Vec<SyncPlan>, Vec<String>)> {
+    let changes = pending_changes(&job, &empty_directories, lockfile)?;
+    if changes.is_empty() {
+        return Ok((job, empty_directories));
+    }
+    let mut selection = Selection {
+        selected: vec![true; changes.len()],
+        changes,
+    };
+    selection.show(&mut output)?;
+    loop {
+        write!(output, "Run these changes? [y/a/r/d PATH/s PATH/l/?] ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            selection.set("/", false);
+            break;
+        }
+        let (command, argument) = line
+            .trim()
+            .split_once(char::is_whitespace)
+            .map(|(command, argument)| (command, argument.trim()))
+            .unwrap_or((line.trim(), ""));
+        match (command, argument) {
+            ("y" | "yes", "") => break,
+            ("a" | "approve", "") => {
+                selection.set("/", true);
+                break;
+            }
+            ("r" | "reject", "") => {
+                selection.set("/", false);
+                break;
+            }
+            ("d" | "deselect", pattern) if !pattern.is_empty() => {
+                let count = selection.set(pattern, false);
+                writeln!(output, "Deselected {count} changes")?;
+            }
+            ("s" | "select", pattern) if !pattern.is_empty() => {
+                let count = selection.set(pattern, true);
+                writeln!(output, "Selected {count} changes")?;
+            }
+            ("l" | "list", "") => selection.show(&mut output)?,
+            _ => writeln!(output, "{HELP}")?,
+        }
+    }
+
+    let rejected = selection.rejected();
+    job.retain(|plan| !rejected.contains(&plan.remote()));
+    empty_directories
+        .retain(|directory| !rejected.iter().any(|path| covers(directory.as_str(), path)));
+    writeln!(
+        output,
+        "Approved {} of {} changes",
+        selection.changes.len() - rejected.len(),
+        selection.changes.len()
+    )?;
+    Ok((job, empty_directories))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::FileMeta;
+    use crate::planning::plan_sync;
+    use fxhash::FxHashMap;
+    use std::path::PathBuf;
+
+    fn job() -> (Vec<SyncPlan>, Vec<String>) {
+        let mut local = FxHashMap::default();
+        local.insert("README.md".to_string(), PathBuf::from("README.md").into());
+        local.insert(
+            "docs/Cargo.toml".to_string(),
+            PathBuf::from("Cargo.toml").into(),
+        );
+        let mut remote = FxHashMap::default();
+        for name in ["old/a.html", "old/b.html", ".thumper.lock"] {
+            remote.insert(
+                name.to_string(),
+                FileMeta {
+                    checksum: None,
+                    content_type: None,
+                    length: None,
+                },
+            );
+        }
+        (
+            plan_sync(&local, &remote, &[], true),
+            vec!["old/".to_string()],
+        )
+    }
+
+    fn review(input: &str) -> (Vec<String>, Vec<String>, String) {
+        let (job, empty_directories) = job();
+        let mut output = vec![];
+        let (job, empty_directories) = review_plan(
+            job,
+            empty_directories,
+            ".thumper.lock",
+            input.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        let mut remotes: Vec<String> = job.iter().map(|plan| plan.remote().to_string()).collect();
+        remotes.sort();
+        (
+            remotes,
+            empty_directories,
+            String::from_utf8(output).unwrap(),
+        )
+    }
+
+    #[test]
+    fn groups_by_action_and_directory() {
+        let (_, _, output) = review("y\n");
+        assert!(output.starts_with(
+            "New files:\n  /\n    [x] + README.md (new file)\n  docs/\n    [x] + docs/Cargo.toml (new file)\nDeletions:\n"
+        ));
+        assert!(
+            output.contains("  /\n    [x] - old/ (empty directory)\n  old/\n    [x] - old/a.html")
+        );
+        assert!(output.ends_with("Approved 5 of 5 changes\n"));
+    }
+
+    #[test]
+    fn deselects_subtrees() {
+        let (remotes, empty_directories, output) = review("d old/b.html\nd docs\ny\n");
+        assert_eq!(remotes, vec![".thumper.lock", "README.md", "old/a.html"]);
+        assert!(empty_directories.is_empty());
+        assert!(output.contains("Deselected 1 changes\nRun"));
+        assert!(output.ends_with("Approved 3 of 5 changes\n"));
+
+        let (remotes, empty_directories, _) = review("d old/\ns old/a.html\nd /\na\n");
+        assert_eq!(remotes.len(), 5);
+        assert_eq!(empty_directories, vec!["old/"]);
+    }
+
+    #[test]
+    fn rejects_everything_without_input() {
+        for input in ["", "r\n"] {
+            let (remotes, empty_directories, output) = review(input);
+            assert_eq!(remotes, vec![".thumper.lock"]);
+            assert!(empty_directories.is_empty());
+            assert!(output.ends_with("Approved 0 of 5 changes\n"));
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::events::{Event, Observer};
+use crate::progress::format_bytes;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// How many of the slowest files the summary lists
+const SLOWEST: usize = 5;
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum SummaryFormat {
+    /// A table on stderr
+    #[default]
+    Text,
+    /// A JSON object on stderr
+    Json,
+    /// No summary
+    None,
+}
+
+/// What a sync did and where its time went. Times of jobs are summed over the workers, so they
+/// can add up to more than the wall time with --concurrency.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DeploySummary {
+    /// A dry run uploads and deletes nothing, so it only counts skipped files
+    pub dry_run: bool,
+    pub uploaded: usize,
+    pub skipped: usize,
+    pub deleted: usize,
+    pub failed: usize,
+    pub bytes_uploaded: u64,
+    pub listing_ms: u64,
+    pub hashing_ms: u64,
+    pub uploading_ms: u64,
+    pub deleting_ms: u64,
+    pub total_ms: u64,
+    pub list_retries: usize,
+    pub slowest: Vec<SlowFile>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SlowFile {
+    pub path: String,
+    pub action: String,
+    pub duration_ms: u64,
+}
+
+/// Gathers a `DeploySummary` from the events of a sync
+pub struct Summarizer {
+    started: Instant,
+    summary: Mutex<DeploySummary>,
+}
+
+impl Summarizer {
+    pub fn new(dry_run: bool) -> Self {
+        Summarizer {
+            started: Instant::now(),
+            summary: Mutex::new(DeploySummary {
+                dry_run,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// The summary so far
+    pub fn summary(&self) -> DeploySummary {
+        let mut summary = match self.summary.lock() {
+            Ok(summary) => summary.clone(),
+            Err(_) => DeploySummary::default(),
+        };
+        summary.total_ms = crate::millis(self.started.elapsed());
+        summary
+    }
+
+    /// Print the summary so far to stderr in `format`
+    pub fn print(&self, format: SummaryFormat) {
+        let summary = self.summary();
+        match format {
+            SummaryFormat::Text => eprint!("{}", summary.table()),
+            SummaryFormat::Json => {
+                if let Ok(json) = serde_json::to_string(&summary) {
+                    eprintln!("{json}");
+                }
+            }
+            SummaryFormat::None => {}
+        }
+    }
+}
+
+impl Observer for Summarizer {
+    fn observe(&self, event: &Event) {
+        let Ok(mut summary) = self.summary.lock() else {
+            return;
+        };
+        match event {
+            Event::ListRetry { .. } => summary.list_retries += 1,
+            Event::ListingDone { duration_ms, .. } => summary.listing_ms += duration_ms,
+            Event::Job {
+                path,
+                action,
+                bytes,
+                hash_ms,
+                duration_ms,
+                dry_run,
+                ..
+            } => {
+                summary.hashing_ms += hash_ms;
+                match *action {
+                    // Printed with the job already, counting it would claim it was done
+                    "put" | "delete" | "trash" if *dry_run => {}
+                    "put" => {
+                        summary.uploaded += 1;
+                        summary.bytes_uploaded += bytes;
+                        summary.uploading_ms += duration_ms.saturating_sub(*hash_ms);
+                    }
+                    "delete" | "trash" => {
+                        summary.deleted += 1;
+                        summary.deleting_ms += duration_ms;
+                    }
+                    _ => summary.skipped += 1,
+                }
+                summary.record_duration(path, action, *duration_ms);
+            }
+            Event::JobFailed { .. } => summary.failed += 1,
+            _ => {}
+        }
+    }
+}
+
+impl DeploySummary {
+    /// Keep the file among the slowest ones if it took long enough
+    fn record_duration(&mut self, path: &str, action: &str, duration_ms: u64) {
+        let at = self
+            .slowest
+            .partition_point(|slow| slow.duration_ms >= duration_ms);
+        if at < SLOWEST {
+            self.slowest.insert(
+                at,
+                SlowFile {
+                    path: path.to_string(),
+                    action: action.to_string(),
+                    duration_ms,
+                },
+            );
+            self.slowest.truncate(SLOWEST);
+        }
+    }
+
+    fn table(&self) -> String {
+        let title = match self.dry_run {
+            true => "Dry run summary, nothing was uploaded or deleted",
+            false => "Deploy summary",
+        };
+        let mut table = format!(
+            "{title}\n  \
+             Uploaded   {} files, {}\n  \
+             Skipped    {} files\n  \
+             Deleted    {} files\n  \
+             Failed     {} files\n  \
+             Listing    {}\n  \
+             Hashing    {}\n  \
+             Uploading  {}\n  \
+             Deleting   {}\n  \
+             Total      {}\n  \
+             Retries    {} listings\n",
+            self.uploaded,
+            format_bytes(self.bytes_uploaded),
+            self.skipped,
+            self.deleted,
+            self.failed,
+            seconds(self.listing_ms),
+            seconds(self.hashing_ms),
+            seconds(self.uploading_ms),
+            seconds(self.deleting_ms),
+            seconds(self.total_ms),
+            self.list_retries,
+        );
+        if !self.slowest.is_empty() {
+            table.push_str("Slowest files\n");
+        }
+        for slow in &self.slowest {
+            table.push_str(
+                format!(
+                    "  {:>9}  {} ({})\n",
+                    seconds(slow.duration_ms),
+                    slow.path,
+                    slow.action
+                )
+                .as_str(),
+            );
+        }
+        table
+    }
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{:.1}s", ms as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planning::Reason;
+
+    fn job<'a>(path: &'a str, action: &'a str, bytes: u64, duration_ms: u64) -> Event<'a> {
+        Event::Job {
+            path,
+            action,
+            reason: Reason::NewFile,
+            bytes,
+            size: bytes,
+            hash_ms: 10,
+            duration_ms,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn sums_jobs_and_keeps_the_slowest() {
+        let summarizer = Summarizer::new(false);
+        let events = [
+            Event::ListRetry {
+                path: "site/",
+                attempt: 1,
+                error: "timeout".into(),
+            },
+            Event::ListingDone {
+                path: "site/",
+                files: 9,
+                duration_ms: 1200,
+            },
+            job("a.css", "put", 2048, 300),
+            job("b.css", "put", 1024, 100),
+            job("c.html", "unchanged", 0, 20),
+            job("d.js", "unchanged", 0, 30),
+            job("e.js", "unchanged", 0, 40),
+            job("f.js", "unchanged", 0, 50),
+            job("old.html", "delete", 0, 500),
+            Event::JobFailed {
+                path: "g.css",
+                error: "HTTP 503".into(),
+            },
+        ];
+        for event in &events {
+            summarizer.observe(event);
+        }
+        let summary = summarizer.summary.into_inner().unwrap();
+        assert_eq!(
+            (summary.uploaded, summary.skipped, summary.deleted),
+            (2, 4, 1)
+        );
+        assert_eq!((summary.failed, summary.list_retries), (1, 1));
+        assert_eq!(summary.bytes_uploaded, 3072);
+        assert_eq!(summary.listing_ms, 1200);
+        assert_eq!(summary.hashing_ms, 70);
+        assert_eq!(summary.uploading_ms, 380);
+        assert_eq!(summary.deleting_ms, 500);
+        let slowest: Vec<_> = summary.slowest.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(slowest, ["old.html", "a.css", "b.css", "f.js", "e.js"]);
+        assert!(summary.table().contains("  Uploaded   2 files, 3.0 KiB\n"));
+        assert!(summary.table().ends_with("      0.0s  e.js (unchanged)\n"));
+    }
+
+    #[test]
+    fn counts_nothing_as_done_in_dry_runs() {
+        let summarizer = Summarizer::new(true);
+        for action in ["put", "delete", "unchanged"] {
+            summarizer.observe(&Event::Job {
+                path: "a.css",
+                action,
+                reason: Reason::NewFile,
+                bytes: 0,
+                size: 2048,
+                hash_ms: 10,
+                duration_ms: 20,
+                dry_run: true,
+            });
+        }
+        let summary = summarizer.summary();
+        assert_eq!(
+            (summary.uploaded, summary.skipped, summary.deleted),
+            (0, 1, 0)
+        );
+        assert!(summary.table().starts_with("Dry run summary, "));
+    }
+}
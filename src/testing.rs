@@ -0,0 +1,436 @@
+use crate::api::{PullZoneClient, StorageZoneClient};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{env, fs, process, thread};
+
+#[derive(Default)]
+struct Zone {
+    files: BTreeMap<String, (Vec<u8>, String)>,
+    directories: BTreeSet<String>,
+    /// Paths that requests fail for, with the status to answer and how many more requests fail
+    failures: BTreeMap<String, (String, usize)>,
+    /// How many directory listings were requested
+    listings: usize,
+}
+
+impl Zone {
+    fn put(&mut self, path: &str, content: Vec<u8>, content_type: String) {
+        let mut parent = path;
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            self.directories.insert(format!("{dir}/"));
+            parent = dir;
+        }
+        self.files.insert(path.to_string(), (content, content_type));
+    }
+
+    fn delete(&mut self, path: &str) -> bool {
+        if path.ends_with('/') || path.is_empty() {
+            let existed = self.directories.remove(path);
+            let before = self.files.len();
+            self.files.retain(|name, _| !name.starts_with(path));
+            self.directories.retain(|name| !name.starts_with(path));
+            existed || before != self.files.len()
+        } else {
+            self.files.remove(path).is_some()
+        }
+    }
+
+    fn list(&self, zone: &str, dir: &str) -> serde_json::Value {
+        let mut children = vec![];
+        let is_child = |name: &str| {
+            name.strip_prefix(dir)
+                .map(|rest| !rest.is_empty() && !rest.trim_end_matches('/').contains('/'))
+                .unwrap_or(false)
+        };
+        for name in self.directories.iter().filter(|name| is_child(name)) {
+            children.push(json!({
+                "StorageZoneName": zone,
+                "Path": format!("/{zone}/{dir}"),
+                "ObjectName": name[dir.len()..].trim_end_matches('/'),
+                "Length": 0,
+                "LastChanged": "2025-04-15T16:52:33.824",
+                "IsDirectory": true,
+                "ContentType": "",
+                "Checksum": null,
+            }));
+        }
+        for (name, (content, content_type)) in self.files.iter().filter(|(name, _)| is_child(name))
+        {
+            children.push(json!({
+                "StorageZoneName": zone,
+                "Path": format!("/{zone}/{dir}"),
+                "ObjectName": &name[dir.len()..],
+                "Length": content.len(),
+                "LastChanged": "2025-04-15T16:52:33.824",
+                "IsDirectory": false,
+                "ContentType": content_type,
+                "Checksum": hex::encode_upper(Sha256::digest(content)),
+            }));
+        }
+        serde_json::Value::Array(children)
+    }
+}
+
+/// An in-memory storage zone served over plain HTTP on localhost.
+pub struct FakeStorageZone {
+    zone: String,
+    endpoint: String,
+    state: Arc<Mutex<Zone>>,
+}
+
+impl FakeStorageZone {
+    pub fn start(zone: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(Zone::default()));
+        let server_state = state.clone();
+        let server_zone = zone.to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = server_state.clone();
+                let zone = server_zone.clone();
+                thread::spawn(move || serve(stream, &zone, &state));
+            }
+        });
+        FakeStorageZone {
+            zone: zone.to_string(),
+            endpoint,
+            state,
+        }
+    }
+
+    pub fn client(&self) -> StorageZoneClient {
+        StorageZoneClient::new(
+            "secret".to_string(),
+            self.endpoint.clone(),
+            self.zone.clone(),
+        )
+    }
+
+    pub fn endpoint(&self) -> String {
+        self.endpoint.clone()
+    }
+
+    pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state.files.get(path).map(|(content, _)| content.clone())
+    }
+
+    pub fn content_type(&self, path: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .files
+            .get(path)
+            .map(|(_, content_type)| content_type.clone())
+    }
+
+    /// How many directory listings were requested so far
+    pub fn listings(&self) -> usize {
+        self.state.lock().unwrap().listings
+    }
+
+    pub fn files(&self) -> Vec<String> {
+        self.state.lock().unwrap().files.keys().cloned().collect()
+    }
+
+    /// Answer every request for `path` with `status`, like `503 Service Unavailable`
+    pub fn fail(&self, path: &str, status: &str) {
+        self.fail_times(path, status, usize::MAX);
+    }
+
+    /// Answer the next `times` requests for `path` with `status`
+    pub fn fail_times(&self, path: &str, status: &str, times: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .insert(path.to_string(), (status.to_string(), times));
+    }
+
+    pub fn insert(&self, path: &str, content: &[u8]) {
+        self.state
+            .lock()
+            .unwrap()
+            .put(path, content.to_vec(), String::new());
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &[u8]) {
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(body);
+}
+
+#[derive(Clone)]
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub content_type: String,
+    /// Headers by lowercase name
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+fn parse_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next()?.to_string(), parts.next()?.to_string());
+    let mut headers = BTreeMap::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).is_err() || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    let content_length = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request {
+        method,
+        target: urlencoding::decode(&target).unwrap().into_owned(),
+        content_type: headers.get("content-type").cloned().unwrap_or_default(),
+        headers,
+        body,
+    })
+}
+
+/// Read a request, answering 401 and returning `None` unless it carries the `secret` AccessKey
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let request = parse_request(stream)?;
+    if request.headers.get("accesskey").map(String::as_str) != Some("secret") {
+        respond(stream, "401 Unauthorized", b"");
+        return None;
+    }
+    Some(request)
+}
+
+fn serve(mut stream: TcpStream, zone: &str, state: &Mutex<Zone>) {
+    let Some(Request {
+        method,
+        target,
+        content_type,
+        headers,
+        body,
+    }) = read_request(&mut stream)
+    else {
+        return;
+    };
+    let Some(path) = target.strip_prefix(&format!("/{zone}/")) else {
+        return respond(&mut stream, "404 Not Found", b"");
+    };
+    let path = path.trim_start_matches('/');
+    let mut state = state.lock().unwrap();
+    if let Some((status, times)) = state.failures.get_mut(path)
+        && *times > 0
+    {
+        *times -= 1;
+        return respond(&mut stream, status, b"");
+    }
+    match method.as_str() {
+        "GET" if path.is_empty() || path.ends_with('/') => {
+            state.listings += 1;
+            let listing = state.list(zone, path);
+            respond(&mut stream, "200 OK", listing.to_string().as_bytes())
+        }
+        "GET" => match state.files.get(path) {
+            Some((content, _)) => {
+                let content = content.clone();
+                respond(&mut stream, "200 OK", &content)
+            }
+            None => respond(&mut stream, "404 Not Found", b""),
+        },
+        "PUT"
+            if headers.get("checksum").is_some_and(|checksum| {
+                !checksum.eq_ignore_ascii_case(&hex::encode(Sha256::digest(&body)))
+            }) =>
+        {
+            respond(&mut stream, "400 Bad Request", b"")
+        }
+        "PUT" => {
+            state.put(path, body, content_type);
+            respond(&mut stream, "201 Created", b"")
+        }
+        "DELETE" if state.delete(path) => respond(&mut stream, "200 OK", b""),
+        "DELETE" => respond(&mut stream, "404 Not Found", b""),
+        _ => respond(&mut stream, "405 Method Not Allowed", b""),
+    }
+}
+
+/// An in-memory stand-in for the pull zone part of the bunny.net API, served over plain HTTP on
+/// localhost.
+pub struct FakePullZoneApi {
+    endpoint: String,
+    origins: Arc<Mutex<BTreeMap<u64, String>>>,
+    /// How often the cache of each pull zone was purged
+    purges: Arc<Mutex<BTreeMap<u64, usize>>>,
+}
+
+impl FakePullZoneApi {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let origins = Arc::new(Mutex::new(BTreeMap::new()));
+        let purges = Arc::new(Mutex::new(BTreeMap::new()));
+        let (server_origins, server_purges) = (origins.clone(), purges.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (origins, purges) = (server_origins.clone(), server_purges.clone());
+                thread::spawn(move || serve_pull_zone(stream, &origins, &purges));
+            }
+        });
+        FakePullZoneApi {
+            endpoint,
+            origins,
+            purges,
+        }
+    }
+
+    pub fn client(&self, pull_zone: u64) -> PullZoneClient {
+        PullZoneClient::new("secret".to_string(), self.endpoint.clone(), pull_zone)
+    }
+
+    pub fn origin_url(&self, pull_zone: u64) -> Option<String> {
+        self.origins.lock().unwrap().get(&pull_zone).cloned()
+    }
+
+    pub fn set_origin_url(&self, pull_zone: u64, origin_url: &str) {
+        self.origins
+            .lock()
+            .unwrap()
+            .insert(pull_zone, origin_url.to_string());
+    }
+
+    pub fn purges(&self, pull_zone: u64) -> usize {
+        self.purges
+            .lock()
+            .unwrap()
+            .get(&pull_zone)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+fn serve_pull_zone(
+    mut stream: TcpStream,
+    origins: &Mutex<BTreeMap<u64, String>>,
+    purges: &Mutex<BTreeMap<u64, usize>>,
+) {
+    let Some(Request {
+        method,
+        target,
+        body,
+        ..
+    }) = read_request(&mut stream)
+    else {
+        return;
+    };
+    let Some(id) = target.strip_prefix("/pullzone/") else {
+        return respond(&mut stream, "404 Not Found", b"");
+    };
+    let (id, purge) = match id.strip_suffix("/purgeCache") {
+        Some(id) => (id, true),
+        None => (id, false),
+    };
+    let Ok(pull_zone) = id.parse::<u64>() else {
+        return respond(&mut stream, "404 Not Found", b"");
+    };
+    let mut origins = origins.lock().unwrap();
+    if purge && method == "POST" && origins.contains_key(&pull_zone) {
+        *purges.lock().unwrap().entry(pull_zone).or_default() += 1;
+        return respond(&mut stream, "204 No Content", b"");
+    }
+    if method == "POST" {
+        let update: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        if let Some(origin_url) = update["OriginUrl"].as_str() {
+            origins.insert(pull_zone, origin_url.to_string());
+        }
+    }
+    match origins.get(&pull_zone) {
+        Some(origin_url) if method == "GET" || method == "POST" => {
+            let zone = json!({ "Id": pull_zone, "OriginUrl": origin_url });
+            respond(&mut stream, "200 OK", zone.to_string().as_bytes())
+        }
+        Some(_) => respond(&mut stream, "405 Method Not Allowed", b""),
+        None => respond(&mut stream, "404 Not Found", b""),
+    }
+}
+
+/// A webhook receiver on localhost that records every request. It answers with the given
+/// statuses in turn, and with 200 OK once they run out.
+pub struct FakeWebhook {
+    url: String,
+    received: Arc<Mutex<Vec<Request>>>,
+}
+
+impl FakeWebhook {
+    pub fn start(statuses: &[&str]) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(vec![]));
+        let server_received = received.clone();
+        let mut statuses: Vec<String> = statuses.iter().rev().map(|s| s.to_string()).collect();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let Some(request) = parse_request(&mut stream) else {
+                    continue;
+                };
+                server_received.lock().unwrap().push(request);
+                let status = statuses.pop().unwrap_or_else(|| "200 OK".to_string());
+                respond(&mut stream, status.as_str(), b"");
+            }
+        });
+        FakeWebhook { url, received }
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    pub fn received(&self) -> Vec<Request> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+/// A directory of its own for a test below the system temporary directory, removed again when
+/// dropped, also when the test fails
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("thumper-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
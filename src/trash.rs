@@ -0,0 +1,398 @@
+use crate::api::StorageZoneClient;
+use crate::cli::{TrashAction, TrashEmptyArgs, TrashListArgs, TrashRestoreArgs};
+use crate::files::{Change, client_for, print_changes};
+use crate::planning::covers;
+use anyhow::{Context, anyhow};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Where sync --trash moves deleted files, relative to the root of the storage zone
+pub const TRASH_PREFIX: &str = ".thumper-trash/";
+pub const DEPLOY_ID_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct Deploy {
+    deploy_id: String,
+    files: Vec<String>,
+}
+
+/// An identifier for a sync that sorts by time, like 20250415T165233Z-3f9a1c. The suffix tells
+/// apart syncs that start within the same second.
+pub fn deploy_id() -> String {
+    static STARTED: AtomicU64 = AtomicU64::new(0);
+    let now = Utc::now();
+    let unique = (
+        now.timestamp_subsec_nanos(),
+        process::id(),
+        STARTED.fetch_add(1, Ordering::Relaxed),
+    );
+    let suffix = fxhash::hash64(&unique) & 0xff_ffff;
+    format!("{}-{suffix:06x}", now.format(DEPLOY_ID_FORMAT))
+}
+
+/// The prefix that files deleted by the deploy `deploy_id` are moved below
+pub fn trash_prefix(deploy_id: &str) -> String {
+    format!("{TRASH_PREFIX}{deploy_id}/")
+}
+
+/// Parse an age like `90m`, `12h`, `30d` or `2w`. An age of 0 or less would match everything, so
+/// it is refused.
+pub fn parse_age(age: &str) -> anyhow::Result<Duration> {
+    let split = age.len() - age.chars().last().map(char::len_utf8).unwrap_or(0);
+    let (amount, unit) = age.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .with_context(|| format!("Invalid age {age}, expected a number and a unit like 30d"))?;
+    if amount <= 0 {
+        return Err(anyhow!("Invalid age {age}, it must be more than 0"));
+    }
+    let duration = match unit {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => {
+            return Err(anyhow!(
+                "Invalid age {age}, the unit must be one of s, m, h, d or w"
+            ));
+        }
+    };
+    duration.ok_or_else(|| anyhow!("Invalid age {age}, it is too long"))
+}
+
+/// Whether the deploy `deploy_id` happened more than `age` before `now`. Deploy ids that are not
+/// timestamps are never old.
+pub fn older_than(deploy_id: &str, age: Duration, now: DateTime<Utc>) -> bool {
+    NaiveDateTime::parse_and_remainder(deploy_id, DEPLOY_ID_FORMAT)
+        .ok()
+        .filter(|(_, suffix)| suffix.is_empty() || suffix.starts_with('-'))
+        .is_some_and(|(time, _)| now - time.and_utc() > age)
+}
+
+fn trashed(
+    client: &StorageZoneClient,
+    deploy_id: Option<&str>,
+    concurrency: usize,
+) -> anyhow::Result<Vec<Deploy>> {
+    let root = deploy_id
+        .map(trash_prefix)
+        .unwrap_or_else(|| TRASH_PREFIX.to_string());
+    let mut deploys: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for file in client.concurrent_discover_files(root.as_str(), &[], concurrency)? {
+        let name = client.name_of(&file);
+        let Some((deploy_id, path)) = name
+            .strip_prefix(TRASH_PREFIX)
+            .and_then(|rest| rest.split_once('/'))
+        else {
+            continue;
+        };
+        let files = deploys.entry(deploy_id.to_string()).or_default();
+        if !file.is_directory {
+            files.push(path.to_string());
+        }
+    }
+    Ok(deploys
+        .into_iter()
+        .map(|(deploy_id, mut files)| {
+            files.sort();
+            Deploy { deploy_id, files }
+        })
+        .collect())
+}
+
+fn restore(
+    client: &StorageZoneClient,
+    deploy_id: &str,
+    paths: &[String],
+    force: bool,
+    dry_run: bool,
+    concurrency: usize,
+) -> anyhow::Result<Vec<Change>> {
+    let deploy = trashed(client, Some(deploy_id), concurrency)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("Nothing in the trash for deploy {deploy_id}"))?;
+    let files: Vec<String> = deploy
+        .files
+        .into_iter()
+        .filter(|file| paths.is_empty() || paths.iter().any(|path| covers(path, file)))
+        .collect();
+    if files.is_empty() {
+        return Err(anyhow!(
+            "No files in deploy {deploy_id} match {}",
+            paths.join(", ")
+        ));
+    }
+    if !force {
+        let paths: Vec<&str> = files.iter().map(String::as_str).collect();
+        let found = client.stat_all(&paths)?;
+        let existing: Vec<&str> = paths
+            .into_iter()
+            .filter(|path| found.contains_key(*path))
+            .collect();
+        if !existing.is_empty() {
+            return Err(anyhow!(
+                "Refusing to overwrite {} files that exist again, pass --force to restore anyway: {}",
+                existing.len(),
+                existing.join(", ")
+            ));
+        }
+    }
+
+    let prefix = trash_prefix(deploy_id);
+    let mut changes = vec![];
+    for file in files {
+        if !dry_run {
+            client.move_file(&format!("{prefix}{file}"), file.as_str())?;
+        }
+        changes.push(Change {
+            path: file,
+            action: "restore",
+            dry_run,
+        });
+    }
+    if !dry_run && paths.is_empty() {
+        client.delete_file(prefix.as_str())?;
+    }
+    Ok(changes)
+}
+
+fn empty(
+    client: &StorageZoneClient,
+    age: Option<Duration>,
+    now: DateTime<Utc>,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Change>> {
+    let mut deploys: Vec<String> = client
+        .ls_dir(TRASH_PREFIX)?
+        .into_iter()
+        .filter(|file| file.is_directory)
+        .map(|file| file.object_name)
+        .filter(|deploy_id| age.is_none_or(|age| older_than(deploy_id, age, now)))
+        .collect();
+    deploys.sort();
+    let mut changes = vec![];
+    for deploy_id in deploys {
+        let prefix = trash_prefix(deploy_id.as_str());
+        if !dry_run {
+            client.delete_file(prefix.as_str())?;
+        }
+        changes.push(Change {
+            path: prefix,
+            action: "delete",
+            dry_run,
+        });
+    }
+    Ok(changes)
+}
+
+fn do_list(args: TrashListArgs) -> anyhow::Result<()> {
+    let TrashListArgs {
+        zone,
+        deploy_id,
+        json,
+        concurrency,
+    } = args;
+    let concurrency = concurrency.unwrap_or_else(num_cpus::get);
+    let client = client_for(zone)?;
+    let deploys = trashed(&client, deploy_id.as_deref(), concurrency)?;
+    if json {
+        println!("{}", serde_json::to_string(&deploys)?);
+        return Ok(());
+    }
+    for Deploy {
+        deploy_id: id,
+        files,
+    } in &deploys
+    {
+        println!("{id}: {} files", files.len());
+        if deploy_id.is_some() {
+            for file in files {
+                println!("  {file}");
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn do_trash(command: TrashAction) -> anyhow::Result<()> {
+    match command {
+        TrashAction::List { args } => do_list(args),
+        TrashAction::Restore { args } => {
+            let TrashRestoreArgs {
+                zone,
+                deploy_id,
+                paths,
+                force,
+                dry_run,
+                json,
+                concurrency,
+            } = args;
+            let concurrency = concurrency.unwrap_or_else(num_cpus::get);
+            let client = client_for(zone)?;
+            let changes = restore(
+                &client,
+                deploy_id.as_str(),
+                &paths,
+                force,
+                dry_run,
+                concurrency,
+            )?;
+            print_changes(&changes, json)
+        }
+        TrashAction::Empty { args } => {
+            let TrashEmptyArgs {
+                zone,
+                older_than,
+                dry_run,
+                json,
+            } = args;
+            let client = client_for(zone)?;
+            let changes = empty(&client, older_than, Utc::now(), dry_run)?;
+            print_changes(&changes, json)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::planning::SyncPlan;
+    use crate::testing::FakeStorageZone;
+    use crate::{ExecuteOptions, execute_sync};
+    use clap::Parser;
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("30d").unwrap(), Duration::days(30));
+        assert_eq!(parse_age("2w").unwrap(), Duration::days(14));
+        assert_eq!(parse_age("90m").unwrap(), Duration::minutes(90));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("").is_err());
+        assert!(parse_age("0d").is_err());
+        assert!(parse_age("-1d").is_err());
+        assert!(parse_age("9999999999999999w").is_err());
+    }
+
+    #[test]
+    fn refuses_ages_that_match_every_deploy_on_the_command_line() {
+        for age in ["--older-than=0d", "--older-than=-1d"] {
+            let args = ["thumper", "trash", "empty", "zone", age];
+            let e = Cli::try_parse_from(args).err().unwrap();
+            assert_eq!(e.kind(), clap::error::ErrorKind::ValueValidation, "{age}");
+        }
+        assert!(
+            Cli::try_parse_from(["thumper", "trash", "empty", "zone", "--older-than=1d"]).is_ok()
+        );
+    }
+
+    #[test]
+    fn deploy_ids_are_unique_and_still_tell_their_age() {
+        let (first, second) = (deploy_id(), deploy_id());
+        assert_ne!(first, second);
+        assert_eq!(first.len(), "20250415T165233Z-3f9a1c".len());
+        let now = NaiveDateTime::parse_from_str("20250415T000000Z", DEPLOY_ID_FORMAT)
+            .unwrap()
+            .and_utc();
+        assert!(older_than(
+            "20250101T000000Z-3f9a1c",
+            Duration::days(30),
+            now
+        ));
+        assert!(older_than("20250101T000000Z", Duration::days(30), now));
+        assert!(!older_than(
+            "20250410T000000Z-3f9a1c",
+            Duration::days(30),
+            now
+        ));
+        assert!(!older_than(
+            "20250101T000000Zbackup",
+            Duration::days(30),
+            now
+        ));
+    }
+
+    #[test]
+    fn trashes_and_restores_deleted_files() {
+        let zone = FakeStorageZone::start("trash-zone");
+        zone.insert("site/old.html", b"old");
+        zone.insert("site/css/old.css", b"body {}");
+        let client = zone.client();
+        let prefix = trash_prefix("20250415T165233Z");
+        let job = vec![
+            SyncPlan::Delete {
+                remote: "site/old.html".into(),
+            },
+            SyncPlan::Delete {
+                remote: "site/css/old.css".into(),
+            },
+        ];
+        let options = ExecuteOptions {
+            trash: Some(prefix.as_str()),
+            ..Default::default()
+        };
+        execute_sync(options, job, &client, ".thumper.lock", 2).unwrap();
+        // site/ and site/css/ are listed once for both files, and copies are checked on upload
+        assert_eq!(zone.listings(), 2);
+        assert_eq!(
+            zone.files(),
+            vec![
+                ".thumper-trash/20250415T165233Z/site/css/old.css",
+                ".thumper-trash/20250415T165233Z/site/old.html"
+            ]
+        );
+
+        let deploys = trashed(&client, None, 2).unwrap();
+        assert_eq!(
+            deploys,
+            vec![Deploy {
+                deploy_id: "20250415T165233Z".into(),
+                files: vec!["site/css/old.css".into(), "site/old.html".into()]
+            }]
+        );
+
+        let partial = restore(
+            &client,
+            "20250415T165233Z",
+            &["site/css".into()],
+            false,
+            false,
+            2,
+        )
+        .unwrap();
+        assert_eq!(partial.len(), 1);
+        assert_eq!(zone.get("site/css/old.css"), Some(b"body {}".to_vec()));
+
+        zone.insert("site/old.html", b"new");
+        assert!(restore(&client, "20250415T165233Z", &[], false, false, 2).is_err());
+        restore(&client, "20250415T165233Z", &[], true, false, 2).unwrap();
+        assert_eq!(zone.files(), vec!["site/css/old.css", "site/old.html"]);
+        assert_eq!(zone.get("site/old.html"), Some(b"old".to_vec()));
+    }
+
+    #[test]
+    fn empties_old_deploys() {
+        let zone = FakeStorageZone::start("trash-empty-zone");
+        zone.insert(".thumper-trash/20250101T000000Z/a.html", b"a");
+        zone.insert(".thumper-trash/20250410T000000Z/b.html", b"b");
+        let client = zone.client();
+        let now = NaiveDateTime::parse_from_str("20250415T000000Z", DEPLOY_ID_FORMAT)
+            .unwrap()
+            .and_utc();
+
+        let planned = empty(&client, Some(Duration::days(30)), now, true).unwrap();
+        assert_eq!(planned.len(), 1);
+        assert_eq!(zone.files().len(), 2);
+
+        empty(&client, Some(Duration::days(30)), now, false).unwrap();
+        assert_eq!(zone.files(), vec![".thumper-trash/20250410T000000Z/b.html"]);
+        empty(&client, None, now, false).unwrap();
+        assert!(zone.files().is_empty());
+    }
+}